@@ -4,6 +4,10 @@
 //! - Fetching JWKS directly from a URL
 //! - Fetching JWKS via OpenID Connect discovery
 
+// the example runs on a native tokio runtime
+#![cfg_attr(target_arch = "wasm32", no_main)]
+#![cfg(not(target_arch = "wasm32"))]
+
 use jwks::Jwks;
 
 #[tokio::main]
@@ -16,8 +20,8 @@ async fn main() {
     let jwks_url = "https://www.googleapis.com/oauth2/v3/certs";
     match Jwks::from_jwks_url(jwks_url).await {
         Ok(jwks) => {
-            println!("✅ Successfully fetched {} keys from JWKS", jwks.keys.len());
-            for (kid, jwk) in &jwks.keys {
+            println!("✅ Successfully fetched {} keys from JWKS", jwks.len());
+            for (kid, jwk) in jwks.iter() {
                 println!("  - Key ID: {}, Algorithm: {:?}", kid, jwk.alg);
             }
         }
@@ -34,9 +38,9 @@ async fn main() {
         Ok(jwks) => {
             println!(
                 "✅ Successfully fetched {} keys via OpenID discovery",
-                jwks.keys.len()
+                jwks.len()
             );
-            for (kid, jwk) in &jwks.keys {
+            for (kid, jwk) in jwks.iter() {
                 println!("  - Key ID: {}, Algorithm: {:?}", kid, jwk.alg);
             }
         }
@@ -4,6 +4,10 @@
 //! Note: This JWT won't have a valid signature since we don't have Google's private key,
 //! but it demonstrates the validation flow
 
+// chrono is only a dev-dependency on native targets
+#![cfg_attr(target_arch = "wasm32", no_main)]
+#![cfg(not(target_arch = "wasm32"))]
+
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use serde_json::json;
 
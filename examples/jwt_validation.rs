@@ -3,6 +3,10 @@
 //! This example demonstrates how to use the jwks library with jsonwebtoken
 //! to validate JWT tokens using keys fetched from a JWKS endpoint.
 
+// the example runs on a native tokio runtime
+#![cfg_attr(target_arch = "wasm32", no_main)]
+#![cfg(not(target_arch = "wasm32"))]
+
 use jsonwebtoken::{decode, decode_header, Validation};
 use jwks::Jwks;
 use serde::{Deserialize, Serialize};
@@ -67,7 +71,7 @@ async fn main() {
     let jwks_url = "https://www.googleapis.com/oauth2/v3/certs";
     let jwks = match Jwks::from_jwks_url(jwks_url).await {
         Ok(jwks) => {
-            println!("✅ Successfully fetched {} keys", jwks.keys.len());
+            println!("✅ Successfully fetched {} keys", jwks.len());
             jwks
         }
         Err(e) => {
@@ -78,7 +82,7 @@ async fn main() {
     };
 
     // Step 3: Get the specific JWK for this JWT
-    let jwk = match jwks.keys.get(kid) {
+    let jwk = match jwks.get(kid) {
         Some(jwk) => {
             println!("✅ Found matching key for Key ID: {}", kid);
             jwk
@@ -86,7 +90,7 @@ async fn main() {
         None => {
             println!("❌ No matching key found for Key ID: {}", kid);
             println!("Available Key IDs:");
-            for available_kid in jwks.keys.keys() {
+            for (available_kid, _) in jwks.iter() {
                 println!("  - {}", available_kid);
             }
             return;
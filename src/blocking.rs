@@ -0,0 +1,193 @@
+//! Synchronous versions of the [`Jwks`] fetch functions, for code that does
+//! not run inside an async runtime.
+//!
+//! Verifying tokens with `jsonwebtoken::decode` is already synchronous, so a
+//! [`Jwks`] loaded here is used exactly like one loaded with the async API.
+
+use serde::de::DeserializeOwned;
+
+use crate::http::{parse_json, parse_jwks, OIDCConfig};
+use crate::{FetchError, FetchPolicy, FetchResponse, Jwks, JwksError};
+
+/// The synchronous counterpart of [`crate::JwksFetcher`].
+///
+/// A default implementation for `reqwest::blocking::Client` is provided when
+/// the `reqwest` feature is enabled.
+pub trait JwksFetcher {
+    /// Fetches `url` with a `GET` request.
+    ///
+    /// Implementations should fail on transport errors and on non-success
+    /// HTTP status codes.
+    fn fetch(&self, url: &str) -> Result<FetchResponse, FetchError>;
+}
+
+impl<T: JwksFetcher + ?Sized> JwksFetcher for &T {
+    fn fetch(&self, url: &str) -> Result<FetchResponse, FetchError> {
+        (**self).fetch(url)
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl JwksFetcher for reqwest::blocking::Client {
+    fn fetch(&self, url: &str) -> Result<FetchResponse, FetchError> {
+        let response = self.get(url).send()?.error_for_status()?;
+        let headers = crate::fetcher::collect_headers(response.headers());
+        let final_url = response.url().to_string();
+        let body = response.bytes()?.to_vec();
+        Ok(FetchResponse {
+            body,
+            headers,
+            url: Some(final_url),
+        })
+    }
+}
+
+/// Blocking version of [`Jwks::from_oidc_url`].
+///
+/// # Arguments
+/// * `oidc_url` - The url with OpenID configuration, e.g. <https://accounts.google.com/.well-known/openid-configuration>
+#[cfg(feature = "reqwest")]
+pub fn from_oidc_url(oidc_url: impl Into<String>) -> Result<Jwks, JwksError> {
+    from_oidc_url_with_client(&reqwest::blocking::Client::default(), oidc_url)
+}
+
+/// Blocking version of [`Jwks::from_oidc_url_with_client`].
+pub fn from_oidc_url_with_client(
+    client: &impl JwksFetcher,
+    oidc_url: impl Into<String>,
+) -> Result<Jwks, JwksError> {
+    from_oidc_url_with_policy(client, oidc_url, &FetchPolicy::default())
+}
+
+/// Blocking version of [`Jwks::from_oidc_url_with_policy`].
+pub fn from_oidc_url_with_policy(
+    client: &impl JwksFetcher,
+    oidc_url: impl Into<String>,
+    policy: &FetchPolicy,
+) -> Result<Jwks, JwksError> {
+    let oidc_url = policy.check_url(&oidc_url.into())?;
+    let oidc_config: OIDCConfig = fetch_json(client, oidc_url.as_str(), policy)?;
+    let jwks_uri = policy.check_jwks_uri(&oidc_url, &oidc_config.jwks_uri)?;
+
+    fetch_jwks(client, jwks_uri.as_str(), policy)
+}
+
+/// Blocking version of [`Jwks::from_jwks_url`].
+///
+/// # Arguments
+/// * `jwks_url` - The url which JWKS info is pulled from, e.g. <https://www.googleapis.com/oauth2/v3/certs>
+#[cfg(feature = "reqwest")]
+pub fn from_jwks_url(jwks_url: impl Into<String>) -> Result<Jwks, JwksError> {
+    from_jwks_url_with_client(&reqwest::blocking::Client::default(), jwks_url)
+}
+
+/// Blocking version of [`Jwks::from_jwks_url_with_client`].
+pub fn from_jwks_url_with_client(
+    client: &impl JwksFetcher,
+    jwks_url: impl Into<String>,
+) -> Result<Jwks, JwksError> {
+    from_jwks_url_with_policy(client, jwks_url, &FetchPolicy::default())
+}
+
+/// Blocking version of [`Jwks::from_jwks_url_with_policy`].
+pub fn from_jwks_url_with_policy(
+    client: &impl JwksFetcher,
+    jwks_url: impl Into<String>,
+    policy: &FetchPolicy,
+) -> Result<Jwks, JwksError> {
+    let jwks_url = policy.check_url(&jwks_url.into())?;
+    fetch_jwks(client, jwks_url.as_str(), policy)
+}
+
+fn fetch_jwks(
+    client: &impl JwksFetcher,
+    jwks_url: &str,
+    policy: &FetchPolicy,
+) -> Result<Jwks, JwksError> {
+    let response = fetch_checked(client, jwks_url, policy)?;
+    parse_jwks(&response, &policy.keys)
+}
+
+fn fetch_json<T: DeserializeOwned>(
+    client: &impl JwksFetcher,
+    url: &str,
+    policy: &FetchPolicy,
+) -> Result<T, JwksError> {
+    parse_json(&fetch_checked(client, url, policy)?)
+}
+
+/// Fetches `url` and checks the url the response was served from.
+fn fetch_checked(
+    client: &impl JwksFetcher,
+    url: &str,
+    policy: &FetchPolicy,
+) -> Result<FetchResponse, JwksError> {
+    let response = client.fetch(url).map_err(JwksError::FetchError)?;
+    policy.check_response(&response)?;
+    Ok(response)
+}
+
+#[cfg(all(test, feature = "reqwest", not(target_arch = "wasm32")))]
+mod tests {
+    use httpmock::prelude::*;
+    use jsonwebtoken::jwk::KeyAlgorithm;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn can_fetch_jwks_from_oidc_url() {
+        let server = MockServer::start();
+        let _ = server.mock(|when, then| {
+            when.method(GET).path("/.well-known/openid-configuration");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(json!({ "jwks_uri": server.url("/certs") }).to_string());
+        });
+        let _ = server.mock(|when, then| {
+            when.method(GET).path("/certs");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(
+                    json!({
+                      "keys": [{
+                        "kty": "EC",
+                        "alg": "ES256",
+                        "crv": "P-256",
+                        "x": "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4",
+                        "y": "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM",
+                        "kid": "blocking-key"
+                      }]
+                    })
+                    .to_string(),
+                );
+        });
+
+        let jwks = from_oidc_url(server.url("/.well-known/openid-configuration")).unwrap();
+        assert_eq!(
+            jwks.get("blocking-key").unwrap().alg,
+            Some(KeyAlgorithm::ES256)
+        );
+
+        let jwks = from_jwks_url(server.url("/certs")).unwrap();
+        assert_eq!(jwks.len(), 1);
+    }
+
+    #[test]
+    fn handles_http_error_response() {
+        let server = MockServer::start();
+        let _ = server.mock(|when, then| {
+            when.method(GET).path("/error");
+            then.status(500);
+        });
+
+        let result = from_jwks_url(server.url("/error"));
+        assert!(matches!(result, Err(JwksError::FetchError(_))));
+    }
+
+    #[test]
+    fn handles_url_without_scheme() {
+        let result = from_jwks_url("example.com/.well-known/jwks.json");
+        assert!(matches!(result, Err(JwksError::InvalidUrlScheme(_))));
+    }
+}
@@ -0,0 +1,492 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use jsonwebtoken::get_current_timestamp;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    http::{fetch_checked, parse_json, OIDCConfig},
+    store::{fetch, JwksStore, StoredJwks},
+    FetchError, FetchPolicy, Jwks, JwksError, JwksFetcher,
+};
+
+/// A file holding the last key set fetched successfully, used when the
+/// identity provider cannot be reached, e.g. while a service restarts during
+/// an outage.
+///
+/// The file stores the OpenID configuration (if any), the JWKS, the time they
+/// were fetched and the JWKS cache headers. A key set loaded from it is
+/// [stale][Jwks::is_stale] and still checked against the [`FetchPolicy`].
+///
+/// A `DiskCache` is also a [`JwksStore`] holding a single key set, e.g. to
+/// share it between the processes of a host. Its file is read and written
+/// synchronously.
+#[derive(Clone, Debug)]
+pub struct DiskCache {
+    path: PathBuf,
+}
+
+/// The contents of a cache file.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CachedJwks {
+    /// The OpenID configuration url the JWKS was discovered from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) oidc_url: Option<String>,
+    /// The OpenID configuration document.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) discovery: Option<serde_json::Value>,
+    pub(crate) jwks_url: String,
+    #[serde(flatten)]
+    pub(crate) stored: StoredJwks,
+    /// When the key set stops being served as a [`JwksStore`] entry, in
+    /// seconds since the epoch. It is still used as a fallback after that.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) expires_at: Option<u64>,
+}
+
+impl CachedJwks {
+    pub(crate) fn new(jwks_url: &str, stored: StoredJwks) -> Self {
+        Self {
+            oidc_url: None,
+            discovery: None,
+            jwks_url: jwks_url.to_string(),
+            stored,
+            expires_at: None,
+        }
+    }
+
+    /// Builds the key set, marked stale.
+    pub(crate) fn to_stale_jwks(&self, policy: &FetchPolicy) -> Result<Jwks, JwksError> {
+        let mut jwks = self.stored.to_jwks(policy)?;
+        jwks.stale_since = Some(self.stored.fetched_at);
+        Ok(jwks)
+    }
+}
+
+impl DiskCache {
+    /// A cache stored at `path`. The file is created on the first successful
+    /// fetch.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The path of the cache file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the cache file. A missing or unreadable file is treated as an
+    /// empty cache.
+    pub(crate) fn read(&self) -> Option<CachedJwks> {
+        let contents = fs::read(&self.path).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    /// Replaces the cache file, through a temporary file so readers never see
+    /// a partial write. Each write has its own temporary file, so concurrent
+    /// writers, in this process or others, do not clobber each other.
+    pub(crate) fn write(&self, cached: &CachedJwks) -> io::Result<()> {
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.subsec_nanos());
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(format!(
+            ".{}.{}.{nanos:x}.tmp",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let written = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp)
+            .and_then(|mut file| file.write_all(&serde_json::to_vec_pretty(cached)?))
+            .and_then(|()| fs::rename(&tmp, &self.path));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written
+    }
+}
+
+impl Jwks {
+    /// A version of [`from_oidc_url_with_policy`][Self::from_oidc_url_with_policy]
+    /// that saves what it fetches to `cache`, and falls back to the cached
+    /// key set for the same `oidc_url` when fetching fails.
+    ///
+    /// Only fetch errors fall back to the cache: urls and keys refused by the
+    /// policy are reported as usual, and the cached `jwks_uri` is checked
+    /// against the policy again. Failing to write the cache is not an error.
+    pub async fn from_oidc_url_with_cache(
+        client: &impl JwksFetcher,
+        oidc_url: impl Into<String>,
+        policy: &FetchPolicy,
+        cache: &DiskCache,
+    ) -> Result<Self, JwksError> {
+        let oidc_url = policy.check_url(&oidc_url.into())?;
+        let fetched = async {
+            let response = fetch_checked(client, oidc_url.as_str(), policy).await?;
+            let discovery: serde_json::Value = parse_json(&response)?;
+            let oidc_config: OIDCConfig = serde_json::from_value(discovery.clone())
+                .map_err(|err| JwksError::FetchError(err.into()))?;
+            let jwks_uri = policy.check_jwks_uri(&oidc_url, &oidc_config.jwks_uri)?;
+
+            let (jwks, stored) = fetch(client, jwks_uri.as_str(), policy).await?;
+            let mut cached = CachedJwks::new(jwks_uri.as_str(), stored);
+            cached.oidc_url = Some(oidc_url.to_string());
+            cached.discovery = Some(discovery);
+            Ok((jwks, cached))
+        }
+        .await;
+
+        settle(fetched, cache, policy, |cached| {
+            if cached.oidc_url.as_deref() != Some(oidc_url.as_str()) {
+                return Ok(false);
+            }
+            // the cache may have been filled under a looser policy
+            policy.check_jwks_uri(&oidc_url, &cached.jwks_url)?;
+            Ok(true)
+        })
+    }
+
+    /// A version of [`from_jwks_url_with_policy`][Self::from_jwks_url_with_policy]
+    /// that saves what it fetches to `cache`, and falls back to the cached
+    /// key set for the same `jwks_url` when fetching fails.
+    pub async fn from_jwks_url_with_cache(
+        client: &impl JwksFetcher,
+        jwks_url: impl Into<String>,
+        policy: &FetchPolicy,
+        cache: &DiskCache,
+    ) -> Result<Self, JwksError> {
+        let jwks_url = policy.check_url(&jwks_url.into())?;
+        let fetched = async {
+            let (jwks, stored) = fetch(client, jwks_url.as_str(), policy).await?;
+            Ok((jwks, CachedJwks::new(jwks_url.as_str(), stored)))
+        }
+        .await;
+
+        settle(fetched, cache, policy, |cached| {
+            Ok(cached.oidc_url.is_none() && cached.jwks_url == jwks_url.as_str())
+        })
+    }
+}
+
+impl JwksStore for DiskCache {
+    async fn get(&self, jwks_url: &str) -> Result<Option<StoredJwks>, FetchError> {
+        let now = get_current_timestamp();
+        let jwks_url = normalize(jwks_url);
+        Ok(self
+            .read()
+            .filter(|cached| cached.jwks_url == jwks_url)
+            .filter(|cached| cached.expires_at.is_some_and(|expires_at| now < expires_at))
+            .map(|cached| cached.stored))
+    }
+
+    async fn put(
+        &self,
+        jwks_url: &str,
+        jwks: &StoredJwks,
+        ttl: Duration,
+    ) -> Result<(), FetchError> {
+        let jwks_url = normalize(jwks_url);
+        let mut cached = CachedJwks::new(&jwks_url, jwks.clone());
+        // keep the discovery of a cache filled by `from_oidc_url_with_cache`
+        if let Some(previous) = self.read().filter(|cached| cached.jwks_url == jwks_url) {
+            cached.oidc_url = previous.oidc_url;
+            cached.discovery = previous.discovery;
+        }
+        cached.expires_at = Some(get_current_timestamp().saturating_add(ttl.as_secs()));
+        Ok(self.write(&cached)?)
+    }
+}
+
+/// The url as the fetch functions save it, so that differently spelled urls
+/// of the same key set share an entry.
+fn normalize(url: &str) -> String {
+    Url::parse(url).map_or_else(|_| url.to_string(), String::from)
+}
+
+/// Saves a successful fetch, or falls back to a matching cached key set when
+/// fetching failed. `matches` fails when the policy refuses the cached urls.
+fn settle(
+    fetched: Result<(Jwks, CachedJwks), JwksError>,
+    cache: &DiskCache,
+    policy: &FetchPolicy,
+    matches: impl FnOnce(&CachedJwks) -> Result<bool, JwksError>,
+) -> Result<Jwks, JwksError> {
+    match fetched {
+        Ok((jwks, cached)) => {
+            let _ = cache.write(&cached);
+            Ok(jwks)
+        }
+        Err(error @ JwksError::FetchError(_)) => match cache.read() {
+            Some(cached) if matches(&cached)? => cached.to_stale_jwks(policy),
+            _ => Err(error),
+        },
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::{cell::Cell, collections::HashMap};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{FetchResponse, KeyType};
+
+    /// Serves a discovery document and a JWKS until it is switched off.
+    struct StubFetcher {
+        online: Cell<bool>,
+    }
+
+    impl JwksFetcher for StubFetcher {
+        async fn fetch(&self, url: &str) -> Result<FetchResponse, FetchError> {
+            if !self.online.get() {
+                return Err("connection refused".into());
+            }
+            let body = if url.ends_with("openid-configuration") {
+                json!({ "jwks_uri": "https://issuer.example/jwks.json" })
+            } else {
+                json!({ "keys": [{
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "kid": "cached",
+                    "x": "tOOp1Sko9BGCpfjK-mAOQF875W-xdWPnHbNtjs0xv3I",
+                    "y": "atwZ-tOsAFi6rQ1DOHzu3x6K6t8jLGAAtxwG8cIaJks"
+                }]})
+            };
+            Ok(FetchResponse {
+                body: body.to_string().into_bytes(),
+                headers: HashMap::from([
+                    ("cache-control".to_string(), "max-age=300".to_string()),
+                    ("x-request-id".to_string(), "1".to_string()),
+                ]),
+                url: None,
+            })
+        }
+    }
+
+    fn cache_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("jwks-{}-{name}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_cached_key_set() {
+        let oidc_url = "https://issuer.example/.well-known/openid-configuration";
+        let cache = DiskCache::new(cache_path("oidc"));
+        let client = StubFetcher {
+            online: Cell::new(false),
+        };
+        let policy = FetchPolicy::default();
+
+        // nothing cached yet
+        assert!(matches!(
+            Jwks::from_oidc_url_with_cache(&client, oidc_url, &policy, &cache).await,
+            Err(JwksError::FetchError(_))
+        ));
+
+        client.online.set(true);
+        let jwks = Jwks::from_oidc_url_with_cache(&client, oidc_url, &policy, &cache)
+            .await
+            .unwrap();
+        assert!(!jwks.is_stale());
+        let cached = cache.read().unwrap();
+        assert_eq!(cached.jwks_url, "https://issuer.example/jwks.json");
+        assert!(cached.discovery.is_some());
+        assert_eq!(
+            cached.stored.headers.get("cache-control").unwrap(),
+            "max-age=300"
+        );
+        assert!(!cached.stored.headers.contains_key("x-request-id"));
+
+        client.online.set(false);
+        let jwks = Jwks::from_oidc_url_with_cache(&client, oidc_url, &policy, &cache)
+            .await
+            .unwrap();
+        assert!(jwks.is_stale());
+        assert_eq!(jwks.stale_since(), Some(cached.stored.fetched_at));
+        assert!(jwks.get("cached").is_some());
+
+        // the cache only answers for the url it was filled from
+        assert!(Jwks::from_jwks_url_with_cache(
+            &client,
+            "https://issuer.example/jwks.json",
+            &policy,
+            &cache
+        )
+        .await
+        .is_err());
+
+        // cached keys are still checked against the policy
+        let mut rsa_only = FetchPolicy::default();
+        rsa_only.keys.allowed_key_types = Some(vec![KeyType::Rsa]);
+        let jwks = Jwks::from_oidc_url_with_cache(&client, oidc_url, &rsa_only, &cache)
+            .await
+            .unwrap();
+        assert!(jwks.is_empty());
+        assert_eq!(jwks.rejected()[0].kid.as_deref(), Some("cached"));
+        let _ = fs::remove_file(cache.path());
+    }
+
+    #[tokio::test]
+    async fn checks_the_cached_jwks_uri_against_the_policy() {
+        let oidc_url = "https://issuer.example/.well-known/openid-configuration";
+        let cache = DiskCache::new(cache_path("jwks-uri"));
+        let client = StubFetcher {
+            online: Cell::new(true),
+        };
+        Jwks::from_oidc_url_with_cache(&client, oidc_url, &FetchPolicy::default(), &cache)
+            .await
+            .unwrap();
+        let mut cached = cache.read().unwrap();
+        cached.jwks_url = "https://other.example/jwks.json".to_string();
+        cache.write(&cached).unwrap();
+
+        client.online.set(false);
+        assert!(matches!(
+            Jwks::from_oidc_url_with_cache(&client, oidc_url, &FetchPolicy::strict(), &cache).await,
+            Err(JwksError::DisallowedHost(_))
+        ));
+        let jwks =
+            Jwks::from_oidc_url_with_cache(&client, oidc_url, &FetchPolicy::default(), &cache)
+                .await
+                .unwrap();
+        assert!(jwks.is_stale());
+        let _ = fs::remove_file(cache.path());
+    }
+
+    #[tokio::test]
+    async fn falls_back_for_non_canonical_urls() {
+        let jwks_url = "https://Issuer.example:443/jwks.json";
+        let cache = DiskCache::new(cache_path("canonical"));
+        let client = StubFetcher {
+            online: Cell::new(true),
+        };
+        let policy = FetchPolicy::default();
+        Jwks::from_jwks_url_with_cache(&client, jwks_url, &policy, &cache)
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.read().unwrap().jwks_url,
+            "https://issuer.example/jwks.json"
+        );
+
+        client.online.set(false);
+        let jwks = Jwks::from_jwks_url_with_cache(&client, jwks_url, &policy, &cache)
+            .await
+            .unwrap();
+        assert!(jwks.is_stale());
+        let jwks = Jwks::from_jwks_url_with_cache(
+            &client,
+            "https://issuer.example/jwks.json",
+            &policy,
+            &cache,
+        )
+        .await
+        .unwrap();
+        assert!(jwks.is_stale());
+
+        // the store answers for every spelling of the url
+        let stored = cache.read().unwrap().stored;
+        cache
+            .put(jwks_url, &stored, Duration::from_secs(300))
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get("https://issuer.example/jwks.json").await.unwrap(),
+            Some(stored)
+        );
+
+        // urls the policy refuses are reported instead of falling back
+        assert!(matches!(
+            Jwks::from_jwks_url_with_cache(
+                &client,
+                "http://issuer.example/jwks.json",
+                &FetchPolicy::strict(),
+                &cache
+            )
+            .await,
+            Err(JwksError::InsecureUrlScheme(_))
+        ));
+        let _ = fs::remove_file(cache.path());
+    }
+
+    #[test]
+    fn concurrent_writes_publish_whole_files() {
+        let cache = DiskCache::new(cache_path("concurrent"));
+        let cached = CachedJwks::new(
+            "https://issuer.example/jwks.json",
+            StoredJwks {
+                jwks: json!({ "keys": [] }),
+                fetched_at: 0,
+                headers: HashMap::new(),
+            },
+        );
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..25 {
+                        cache.write(&cached).unwrap();
+                        assert!(cache.read().is_some());
+                    }
+                });
+            }
+        });
+
+        let name = cache.path().file_name().unwrap().to_str().unwrap();
+        let leftovers = fs::read_dir(cache.path().parent().unwrap())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+                file_name.starts_with(name) && file_name.ends_with(".tmp")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+        let _ = fs::remove_file(cache.path());
+    }
+
+    #[tokio::test]
+    async fn serves_as_a_store() {
+        let jwks_url = "https://issuer.example/jwks.json";
+        let cache = DiskCache::new(cache_path("store"));
+        let client = StubFetcher {
+            online: Cell::new(true),
+        };
+        let policy = FetchPolicy::default();
+        let (_, stored) = fetch(&client, jwks_url, &policy).await.unwrap();
+
+        assert_eq!(cache.get(jwks_url).await.unwrap(), None);
+        cache
+            .put(jwks_url, &stored, Duration::from_secs(300))
+            .await
+            .unwrap();
+        assert_eq!(cache.get(jwks_url).await.unwrap(), Some(stored.clone()));
+        assert_eq!(
+            cache.get("https://other.example/jwks.json").await.unwrap(),
+            None
+        );
+
+        // an expired entry is still a fallback
+        cache.put(jwks_url, &stored, Duration::ZERO).await.unwrap();
+        assert_eq!(cache.get(jwks_url).await.unwrap(), None);
+        client.online.set(false);
+        let jwks = Jwks::from_jwks_url_with_cache(&client, jwks_url, &policy, &cache)
+            .await
+            .unwrap();
+        assert!(jwks.is_stale());
+        let _ = fs::remove_file(cache.path());
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::{hash_map::Entry, HashSet};
+
+use jsonwebtoken::jwk;
+
+use crate::{Jwk, Jwks, JwksError};
+
+/// A key in a [`KeySetDiff`], identified by its `kid` and RFC 7638
+/// thumbprint.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KeyRef {
+    /// The key id.
+    pub kid: String,
+    /// The RFC 7638 thumbprint of the key, or `None` for symmetric `oct`
+    /// keys, whose thumbprint is a hash of the secret itself. Only computed
+    /// with the `thumbprint` feature, which `http` enables.
+    pub thumbprint: Option<String>,
+}
+
+impl KeyRef {
+    pub(crate) fn new(kid: &str, jwk: &Jwk) -> Self {
+        let thumbprint = match jwk.params {
+            jwk::AlgorithmParameters::OctetKey(_) => None,
+            #[cfg(feature = "thumbprint")]
+            _ => Some(jwk.thumbprint()),
+            #[cfg(not(feature = "thumbprint"))]
+            _ => None,
+        };
+        Self {
+            kid: kid.to_string(),
+            thumbprint,
+        }
+    }
+}
+
+/// A key whose `kid` was kept but whose key material changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangedKey {
+    /// The key id.
+    pub kid: String,
+    /// The thumbprint of the previous key, as in [`KeyRef::thumbprint`].
+    pub old_thumbprint: Option<String>,
+    /// The thumbprint of the new key, as in [`KeyRef::thumbprint`].
+    pub new_thumbprint: Option<String>,
+}
+
+/// The differences between two key sets.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeySetDiff {
+    /// Keys only in the new set.
+    pub added: Vec<KeyRef>,
+    /// Keys only in the old set.
+    pub removed: Vec<KeyRef>,
+    /// Keys present in both sets under the same `kid` with different key
+    /// material.
+    pub changed: Vec<ChangedKey>,
+}
+
+impl KeySetDiff {
+    /// Whether both key sets hold the same keys.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Compares `old` with `new`, key by key.
+    pub(crate) fn between(old: &Jwks, new: &Jwks) -> Self {
+        // keys are told apart by their key material, even when no thumbprint
+        // is reported
+        let only_in = |jwks: &Jwks, other: &Jwks| -> Vec<KeyRef> {
+            let mut only: Vec<(&str, &Jwk)> = Vec::new();
+            for (kid, jwk) in jwks.iter() {
+                let same = |key: &Jwk| key.params == jwk.params;
+                let listed = only.iter().any(|(k, key)| *k == kid && same(key));
+                if !listed && !other.keys_with_kid(kid).any(same) {
+                    only.push((kid, jwk));
+                }
+            }
+            let mut keys: Vec<KeyRef> = only
+                .into_iter()
+                .map(|(kid, jwk)| KeyRef::new(kid, jwk))
+                .collect();
+            keys.sort();
+            keys
+        };
+        let mut added = only_in(new, old);
+        let mut removed = only_in(old, new);
+
+        // a kid losing exactly one key and gaining exactly one is a change
+        let count = |keys: &[KeyRef], kid: &str| keys.iter().filter(|key| key.kid == kid).count();
+        let changed: Vec<ChangedKey> = removed
+            .iter()
+            .filter(|gone| count(&removed, &gone.kid) == 1 && count(&added, &gone.kid) == 1)
+            .filter_map(|gone| {
+                let replacement = added.iter().find(|key| key.kid == gone.kid)?;
+                Some(ChangedKey {
+                    kid: gone.kid.clone(),
+                    old_thumbprint: gone.thumbprint.clone(),
+                    new_thumbprint: replacement.thumbprint.clone(),
+                })
+            })
+            .collect();
+        let unchanged = |key: &KeyRef| changed.iter().all(|change| change.kid != key.kid);
+        added.retain(unchanged);
+        removed.retain(unchanged);
+
+        Self {
+            added,
+            removed,
+            changed,
+        }
+    }
+}
+
+/// What [`Jwks::merge`] does with a key whose `kid` is already used by a
+/// different key. Keys identical to one already in the set are always
+/// skipped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// Fail with [`JwksError::DuplicateKeyId`] and leave the set unchanged.
+    Error,
+    /// Keep the existing keys and ignore the merged ones.
+    KeepExisting,
+    /// Replace the existing keys with the merged ones.
+    Replace,
+    /// Keep both keys, as with [`DuplicateKids::KeepAll`][crate::DuplicateKids::KeepAll].
+    #[default]
+    KeepBoth,
+}
+
+#[allow(deprecated)]
+impl Jwks {
+    /// Compares this key set with a `newer` one.
+    pub fn diff(&self, newer: &Jwks) -> KeySetDiff {
+        KeySetDiff::between(self, newer)
+    }
+
+    /// Adds the keys of `other` to this set, e.g. to accept tokens from two
+    /// identity providers during a migration.
+    pub fn merge(&mut self, other: Jwks, on_conflict: MergeConflict) -> Result<(), JwksError> {
+        let existing: HashSet<String> = self.keys.keys().cloned().collect();
+        let clashes = |jwks: &Jwks, kid: &str, jwk: &Jwk| {
+            existing.contains(kid) && jwks.keys_with_kid(kid).all(|key| key.params != jwk.params)
+        };
+        if on_conflict == MergeConflict::Error {
+            if let Some((kid, _)) = other.iter().find(|(kid, jwk)| clashes(self, kid, jwk)) {
+                return Err(JwksError::DuplicateKeyId(kid.to_string()));
+            }
+        }
+
+        if on_conflict == MergeConflict::Replace {
+            // every key of `other` is added below, including the ones
+            // sharing their kid with each other
+            for kid in other.keys.keys() {
+                self.keys.remove(kid);
+                self.duplicates
+                    .retain(|(duplicate_kid, _)| duplicate_kid != kid);
+            }
+        }
+
+        for (kid, jwk) in other.keys.into_iter().chain(other.duplicates) {
+            if self.keys_with_kid(&kid).any(|key| key.params == jwk.params) {
+                continue;
+            }
+            match self.keys.entry(kid) {
+                Entry::Vacant(entry) => {
+                    entry.insert(jwk);
+                }
+                Entry::Occupied(entry) => match on_conflict {
+                    MergeConflict::KeepExisting if existing.contains(entry.key()) => {}
+                    _ => self.duplicates.push((entry.key().clone(), jwk)),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps only the keys for which `f` returns `true`.
+    pub fn retain(&mut self, mut f: impl FnMut(&str, &Jwk) -> bool) {
+        self.keys.retain(|kid, jwk| f(kid, jwk));
+        self.duplicates.retain(|(kid, jwk)| f(kid, jwk));
+        // a key sharing the kid of a removed key takes its place
+        let mut index = 0;
+        while index < self.duplicates.len() {
+            if self.keys.contains_key(&self.duplicates[index].0) {
+                index += 1;
+            } else {
+                let (kid, jwk) = self.duplicates.remove(index);
+                self.keys.insert(kid, jwk);
+            }
+        }
+    }
+
+    /// Returns a copy of this set with only the keys for which `f` returns
+    /// `true`.
+    pub fn filter(&self, f: impl FnMut(&str, &Jwk) -> bool) -> Jwks {
+        let mut jwks = self.clone();
+        jwks.retain(f);
+        jwks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn jwks(keys: serde_json::Value) -> Jwks {
+        Jwks::from_jwk_set(serde_json::from_value(json!({ "keys": keys })).unwrap()).unwrap()
+    }
+
+    fn oct(kid: &str, k: &str) -> serde_json::Value {
+        json!({ "kty": "oct", "kid": kid, "k": k })
+    }
+
+    #[test]
+    fn diffs_key_sets_by_kid_and_thumbprint() {
+        let old = jwks(json!([
+            oct("kept", "a2VwdA"),
+            oct("rotated", "b2xk"),
+            oct("gone", "Z29uZQ")
+        ]));
+        let new = jwks(json!([
+            oct("kept", "a2VwdA"),
+            oct("rotated", "bmV3"),
+            oct("fresh", "ZnJlc2g")
+        ]));
+
+        let diff = KeySetDiff::between(&old, &new);
+        assert_eq!(
+            diff.added
+                .iter()
+                .map(|key| key.kid.as_str())
+                .collect::<Vec<_>>(),
+            ["fresh"]
+        );
+        assert_eq!(
+            diff.removed
+                .iter()
+                .map(|key| key.kid.as_str())
+                .collect::<Vec<_>>(),
+            ["gone"]
+        );
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].kid, "rotated");
+        // symmetric keys are told apart, but their secrets are not reported
+        assert_eq!(diff.changed[0].old_thumbprint, None);
+        assert_eq!(diff.changed[0].new_thumbprint, None);
+        assert_eq!(diff.added[0].thumbprint, None);
+
+        assert!(KeySetDiff::between(&old, &old).is_empty());
+
+        let ec =
+            |x: &str, y: &str| json!({ "kty": "EC", "crv": "P-256", "kid": "ec", "x": x, "y": y });
+        let old = jwks(json!([ec(
+            "tOOp1Sko9BGCpfjK-mAOQF875W-xdWPnHbNtjs0xv3I",
+            "atwZ-tOsAFi6rQ1DOHzu3x6K6t8jLGAAtxwG8cIaJks"
+        )]));
+        let new = jwks(json!([ec(
+            "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4",
+            "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM"
+        )]));
+        let diff = KeySetDiff::between(&old, &new);
+        assert_eq!(diff.changed.len(), 1);
+        #[cfg(feature = "thumbprint")]
+        assert_eq!(
+            diff.changed[0].old_thumbprint,
+            Some(old.get("ec").unwrap().thumbprint())
+        );
+        #[cfg(feature = "thumbprint")]
+        assert_eq!(
+            diff.changed[0].new_thumbprint,
+            Some(new.get("ec").unwrap().thumbprint())
+        );
+    }
+
+    #[test]
+    fn merges_key_sets() {
+        let ours = jwks(json!([
+            oct("shared", "b3Vycw"),
+            oct("same", "c2FtZQ"),
+            oct("ours", "MQ")
+        ]));
+        let theirs = jwks(json!([
+            oct("shared", "dGhlaXJz"),
+            oct("same", "c2FtZQ"),
+            oct("theirs", "Mg")
+        ]));
+        let kids = |jwks: &Jwks| {
+            let mut kids: Vec<_> = jwks.iter().map(|(kid, _)| kid.to_string()).collect();
+            kids.sort();
+            kids
+        };
+
+        let mut merged = ours.clone();
+        assert!(matches!(
+            merged.merge(theirs.clone(), MergeConflict::Error),
+            Err(JwksError::DuplicateKeyId(kid)) if kid == "shared"
+        ));
+        assert_eq!(merged.len(), 3);
+
+        let mut merged = ours.clone();
+        merged
+            .merge(theirs.clone(), MergeConflict::KeepBoth)
+            .unwrap();
+        assert_eq!(
+            kids(&merged),
+            ["ours", "same", "shared", "shared", "theirs"]
+        );
+
+        let mut merged = ours.clone();
+        merged
+            .merge(theirs.clone(), MergeConflict::KeepExisting)
+            .unwrap();
+        assert_eq!(kids(&merged), ["ours", "same", "shared", "theirs"]);
+        assert_eq!(
+            merged.get("shared").unwrap().params,
+            ours.get("shared").unwrap().params
+        );
+
+        let mut merged = ours.clone();
+        merged
+            .merge(theirs.clone(), MergeConflict::Replace)
+            .unwrap();
+        assert_eq!(
+            merged.get("shared").unwrap().params,
+            theirs.get("shared").unwrap().params
+        );
+        assert_eq!(merged.keys_with_kid("shared").count(), 1);
+    }
+
+    #[test]
+    fn merges_keys_sharing_a_kid() {
+        let ours = jwks(json!([oct("shared", "b3Vycw"), oct("ours", "MQ")]));
+        let theirs = jwks(json!([
+            oct("shared", "Zmlyc3Q"),
+            oct("shared", "c2Vjb25k"),
+            oct("new", "Mg"),
+            oct("new", "Mw")
+        ]));
+        let shared = |jwks: &Jwks, kid: &str| {
+            let mut keys: Vec<_> = jwks
+                .keys_with_kid(kid)
+                .map(|jwk| serde_json::to_string(&jwk.params).unwrap())
+                .collect();
+            keys.sort();
+            keys
+        };
+
+        let mut merged = ours.clone();
+        assert!(matches!(
+            merged.merge(theirs.clone(), MergeConflict::Error),
+            Err(JwksError::DuplicateKeyId(kid)) if kid == "shared"
+        ));
+        assert_eq!(merged.len(), 2);
+
+        let mut merged = ours.clone();
+        merged
+            .merge(theirs.clone(), MergeConflict::KeepExisting)
+            .unwrap();
+        assert_eq!(shared(&merged, "shared"), shared(&ours, "shared"));
+        assert_eq!(shared(&merged, "new"), shared(&theirs, "new"));
+
+        let mut merged = ours.clone();
+        merged
+            .merge(theirs.clone(), MergeConflict::Replace)
+            .unwrap();
+        assert_eq!(shared(&merged, "shared"), shared(&theirs, "shared"));
+        assert_eq!(shared(&merged, "new"), shared(&theirs, "new"));
+        assert_eq!(merged.len(), 5);
+
+        let mut merged = ours.clone();
+        merged
+            .merge(theirs.clone(), MergeConflict::KeepBoth)
+            .unwrap();
+        assert_eq!(merged.keys_with_kid("shared").count(), 3);
+        assert_eq!(shared(&merged, "new"), shared(&theirs, "new"));
+
+        // keys of `other` already in the set are not added twice
+        let mut merged = theirs.clone();
+        merged
+            .merge(theirs.clone(), MergeConflict::Replace)
+            .unwrap();
+        assert_eq!(merged.len(), theirs.len());
+    }
+
+    #[test]
+    fn retains_and_filters_keys() {
+        let mut both = jwks(json!([oct("shared", "b3Vycw"), oct("other", "MQ")]));
+        both.merge(
+            jwks(json!([oct("shared", "dGhlaXJz")])),
+            MergeConflict::KeepBoth,
+        )
+        .unwrap();
+        let first = both.get("shared").unwrap().params.clone();
+
+        let others = both.filter(|kid, _| kid == "other");
+        assert_eq!(others.len(), 1);
+        assert_eq!(both.len(), 3);
+
+        // removing the first "shared" key promotes the second one
+        both.retain(|_, jwk| jwk.params != first);
+        assert_eq!(both.len(), 2);
+        assert_ne!(both.get("shared").unwrap().params, first);
+        assert_eq!(both.keys_with_kid("shared").count(), 1);
+    }
+}
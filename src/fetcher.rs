@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use crate::FetchError;
+
+/// A successful response from a [`JwksFetcher`].
+#[derive(Clone, Debug, Default)]
+pub struct FetchResponse {
+    /// The raw response body.
+    pub body: Vec<u8>,
+    /// The response headers, keyed by lower-cased header name.
+    pub headers: HashMap<String, String>,
+    /// The url the response was served from, when the fetcher followed
+    /// redirects to get it. It is checked against the
+    /// [`FetchPolicy`][crate::FetchPolicy] like the requested url.
+    pub url: Option<String>,
+}
+
+impl FetchResponse {
+    /// Looks up a response header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+}
+
+/// Retrieves OpenID configuration documents and JWKS over the network.
+///
+/// A default implementation for `reqwest::Client` is provided behind the
+/// `reqwest` feature. Implement this trait to fetch through another HTTP
+/// client, a sidecar proxy or an in-process stub.
+///
+/// The returned future is not required to be `Send`, so that fetchers for
+/// single-threaded runtimes (e.g. WebAssembly) can be used.
+#[allow(async_fn_in_trait)]
+pub trait JwksFetcher {
+    /// Fetches `url` with a `GET` request.
+    ///
+    /// Implementations should fail on transport errors and on non-success
+    /// HTTP status codes.
+    async fn fetch(&self, url: &str) -> Result<FetchResponse, FetchError>;
+}
+
+impl<T: JwksFetcher + ?Sized> JwksFetcher for &T {
+    async fn fetch(&self, url: &str) -> Result<FetchResponse, FetchError> {
+        (**self).fetch(url).await
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl JwksFetcher for reqwest::Client {
+    async fn fetch(&self, url: &str) -> Result<FetchResponse, FetchError> {
+        let response = self.get(url).send().await?.error_for_status()?;
+        let headers = collect_headers(response.headers());
+        let final_url = response.url().to_string();
+        let body = response.bytes().await?.to_vec();
+        Ok(FetchResponse {
+            body,
+            headers,
+            url: Some(final_url),
+        })
+    }
+}
+
+#[cfg(feature = "reqwest")]
+pub(crate) fn collect_headers(header_map: &reqwest::header::HeaderMap) -> HashMap<String, String> {
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for (name, value) in header_map {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        headers
+            .entry(name.as_str().to_string())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+    headers
+}
@@ -0,0 +1,188 @@
+use jsonwebtoken::jwk;
+use serde::{de::DeserializeOwned, Deserialize};
+use url::Url;
+
+use crate::{
+    is_supported_curve, FetchPolicy, FetchResponse, JwkError, Jwks, JwksError, JwksFetcher,
+    KeyPolicy, KeyType, RejectedKey,
+};
+
+#[derive(Deserialize)]
+pub(crate) struct OIDCConfig {
+    pub(crate) jwks_uri: String,
+}
+
+impl Jwks {
+    /// # Arguments
+    /// * `oidc_url` - The url with OpenID configuration, e.g. <https://accounts.google.com/.well-known/openid-configuration>
+    #[cfg(feature = "reqwest")]
+    pub async fn from_oidc_url(oidc_url: impl Into<String>) -> Result<Self, JwksError> {
+        Self::from_oidc_url_with_client(&reqwest::Client::default(), oidc_url).await
+    }
+
+    /// A version of `from_oidc_url` that allows for
+    /// passing in a custom [`JwksFetcher`], such as a `reqwest::Client`.
+    pub async fn from_oidc_url_with_client(
+        client: &impl JwksFetcher,
+        oidc_url: impl Into<String>,
+    ) -> Result<Self, JwksError> {
+        Self::from_oidc_url_with_policy(client, oidc_url, &FetchPolicy::default()).await
+    }
+
+    /// A version of [`from_oidc_url_with_client`][Self::from_oidc_url_with_client]
+    /// that checks both the OpenID configuration url and the `jwks_uri` it
+    /// points at against a [`FetchPolicy`].
+    pub async fn from_oidc_url_with_policy(
+        client: &impl JwksFetcher,
+        oidc_url: impl Into<String>,
+        policy: &FetchPolicy,
+    ) -> Result<Self, JwksError> {
+        let jwks_uri = discover_jwks_uri(client, &oidc_url.into(), policy).await?;
+        Self::fetch_jwks(client, jwks_uri.as_str(), policy).await
+    }
+
+    /// # Arguments
+    /// * `jwks_url` - The url which JWKS info is pulled from, e.g. <https://www.googleapis.com/oauth2/v3/certs>
+    #[cfg(feature = "reqwest")]
+    pub async fn from_jwks_url(jwks_url: impl Into<String>) -> Result<Self, JwksError> {
+        Self::from_jwks_url_with_client(&reqwest::Client::default(), jwks_url).await
+    }
+
+    /// A version of `from_jwks_url` that allows for
+    /// passing in a custom [`JwksFetcher`], such as a `reqwest::Client`.
+    pub async fn from_jwks_url_with_client(
+        client: &impl JwksFetcher,
+        jwks_url: impl Into<String>,
+    ) -> Result<Self, JwksError> {
+        Self::from_jwks_url_with_policy(client, jwks_url, &FetchPolicy::default()).await
+    }
+
+    /// A version of [`from_jwks_url_with_client`][Self::from_jwks_url_with_client]
+    /// that checks the url against a [`FetchPolicy`].
+    pub async fn from_jwks_url_with_policy(
+        client: &impl JwksFetcher,
+        jwks_url: impl Into<String>,
+        policy: &FetchPolicy,
+    ) -> Result<Self, JwksError> {
+        let jwks_url = policy.check_url(&jwks_url.into())?;
+        Self::fetch_jwks(client, jwks_url.as_str(), policy).await
+    }
+
+    async fn fetch_jwks(
+        client: &impl JwksFetcher,
+        jwks_url: &str,
+        policy: &FetchPolicy,
+    ) -> Result<Self, JwksError> {
+        let response = fetch_checked(client, jwks_url, policy).await?;
+        parse_jwks(&response, &policy.keys)
+    }
+}
+
+/// Fetches the OpenID configuration at `oidc_url` and returns its checked
+/// `jwks_uri`.
+pub(crate) async fn discover_jwks_uri(
+    client: &impl JwksFetcher,
+    oidc_url: &str,
+    policy: &FetchPolicy,
+) -> Result<Url, JwksError> {
+    let oidc_url = policy.check_url(oidc_url)?;
+    let oidc_config: OIDCConfig = fetch_json(client, oidc_url.as_str(), policy).await?;
+    policy.check_jwks_uri(&oidc_url, &oidc_config.jwks_uri)
+}
+
+async fn fetch_json<T: DeserializeOwned>(
+    client: &impl JwksFetcher,
+    url: &str,
+    policy: &FetchPolicy,
+) -> Result<T, JwksError> {
+    parse_json(&fetch_checked(client, url, policy).await?)
+}
+
+/// Fetches `url` and checks the url the response was served from, so a
+/// redirect cannot lead outside the [`FetchPolicy`].
+pub(crate) async fn fetch_checked(
+    client: &impl JwksFetcher,
+    url: &str,
+    policy: &FetchPolicy,
+) -> Result<FetchResponse, JwksError> {
+    let response = client.fetch(url).await.map_err(JwksError::FetchError)?;
+    policy.check_response(&response)?;
+    Ok(response)
+}
+
+pub(crate) fn parse_json<T: DeserializeOwned>(response: &FetchResponse) -> Result<T, JwksError> {
+    serde_json::from_slice(&response.body).map_err(|err| JwksError::FetchError(err.into()))
+}
+
+/// Parses a JWKS response body and checks its keys against `policy`. With
+/// the `x509` feature, key parameters missing from a key are first taken from
+/// its `x5c` leaf certificate.
+///
+/// Keys on curves `jsonwebtoken` cannot represent (e.g. X25519) are left out
+/// as [`JwkError::UnsupportedCurve`] instead of failing to deserialize.
+pub(crate) fn parse_jwks(response: &FetchResponse, policy: &KeyPolicy) -> Result<Jwks, JwksError> {
+    let jwks: serde_json::Value = parse_json(response)?;
+    let keys = match jwks.get("keys") {
+        Some(serde_json::Value::Array(keys)) => keys,
+        // let deserialization report the missing or malformed `keys`
+        _ => {
+            let jwk_set: jwk::JwkSet =
+                serde_json::from_value(jwks).map_err(|err| JwksError::FetchError(err.into()))?;
+            return Jwks::from_jwk_set_with_policy(jwk_set, policy);
+        }
+    };
+
+    let mut parsed = Vec::new();
+    let mut rejected = Vec::new();
+    for (index, key) in keys.iter().enumerate() {
+        if let Err(error) = check_curve(index, key) {
+            if policy.fail_on_violation {
+                return Err(error.into());
+            }
+            rejected.push(RejectedKey {
+                index,
+                kid: key_id(key).map(str::to_string),
+                error,
+            });
+            continue;
+        }
+
+        #[cfg_attr(not(feature = "x509"), allow(unused_mut))]
+        let mut key = key.clone();
+        #[cfg(feature = "x509")]
+        crate::x509::fill_params_from_x5c(&mut key, false);
+        let jwk: jwk::Jwk =
+            serde_json::from_value(key).map_err(|err| JwksError::FetchError(err.into()))?;
+        parsed.push((index, jwk));
+    }
+
+    Jwks::from_indexed_keys(
+        parsed.iter().map(|(index, jwk)| (*index, jwk)),
+        rejected,
+        policy,
+    )
+}
+
+fn key_id(key: &serde_json::Value) -> Option<&str> {
+    key.get("kid").and_then(serde_json::Value::as_str)
+}
+
+/// Checks the curve of the key at `index`, which is named by its position
+/// when it has no `kid`.
+fn check_curve(index: usize, key: &serde_json::Value) -> Result<(), JwkError> {
+    let kty = match key.get("kty").and_then(serde_json::Value::as_str) {
+        Some("EC") => KeyType::Ec,
+        Some("OKP") => KeyType::Okp,
+        _ => return Ok(()),
+    };
+    let Some(crv) = key.get("crv").and_then(serde_json::Value::as_str) else {
+        return Ok(());
+    };
+    if is_supported_curve(kty, crv) {
+        return Ok(());
+    }
+    Err(JwkError::UnsupportedCurve {
+        key_id: key_id(key).map_or_else(|| format!("keys[{index}]"), str::to_string),
+        curve: crv.to_string(),
+    })
+}
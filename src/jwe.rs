@@ -0,0 +1,778 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes128Gcm, Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{TokenData, Validation};
+use rsa::{BigUint, Oaep, RsaPrivateKey};
+use serde::{de::DeserializeOwned, Deserialize};
+use sha2::{Digest, Sha256, Sha512};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::{Jwks, VerifyError};
+
+/// An error decrypting a JWE.
+#[derive(Debug, Error)]
+pub enum JweError {
+    /// The token is not a well-formed compact JWE.
+    #[error("invalid JWE: {0}")]
+    InvalidFormat(String),
+
+    /// The `alg`, `enc` or `zip` of the token is not supported.
+    #[error("unsupported JWE algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+
+    /// A private key could not be parsed.
+    #[error("could not read the decryption key {key_id:?}: {reason}")]
+    InvalidKey {
+        key_id: Option<String>,
+        reason: String,
+    },
+
+    /// No key in the set can decrypt the token.
+    #[error("no decryption key matches the token")]
+    NoMatchingKey,
+
+    /// The content encryption key could not be recovered, or the ciphertext
+    /// failed authentication.
+    #[error("decryption failed")]
+    DecryptionFailed,
+
+    /// The decrypted JWS failed verification.
+    #[error("the inner token is invalid: {0}")]
+    InvalidInnerToken(#[source] VerifyError),
+}
+
+/// A private JWK used to decrypt JWEs (`use: enc`).
+///
+/// Supported keys are RSA (`RSA-OAEP`, `RSA-OAEP-256`), EC P-256 and OKP
+/// X25519 (`ECDH-ES`, `ECDH-ES+A128KW`, `ECDH-ES+A256KW`) and `oct`
+/// (`A128KW`, `A256KW`).
+#[derive(Clone)]
+pub struct DecryptionJwk {
+    /// The key id.
+    pub kid: Option<String>,
+    /// The key management algorithm the key is restricted to, if any.
+    pub alg: Option<String>,
+    key: PrivateKey,
+}
+
+#[derive(Clone)]
+enum PrivateKey {
+    Rsa(Box<RsaPrivateKey>),
+    P256(p256::SecretKey),
+    X25519(x25519_dalek::StaticSecret),
+    Oct(Zeroizing<Vec<u8>>),
+}
+
+/// The private JWK members needed for decryption.
+#[derive(Deserialize)]
+struct PrivateJwk {
+    kty: String,
+    kid: Option<String>,
+    alg: Option<String>,
+    crv: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    d: Option<String>,
+    p: Option<String>,
+    q: Option<String>,
+    k: Option<String>,
+}
+
+impl DecryptionJwk {
+    /// Parses a private JWK.
+    pub fn from_json(jwk: &serde_json::Value) -> Result<Self, JweError> {
+        let jwk: PrivateJwk =
+            serde_json::from_value(jwk.clone()).map_err(|err| JweError::InvalidKey {
+                key_id: None,
+                reason: err.to_string(),
+            })?;
+        let invalid = |reason: &str| JweError::InvalidKey {
+            key_id: jwk.kid.clone(),
+            reason: reason.to_string(),
+        };
+        let param = |value: &Option<String>, name: &str| {
+            let value = value
+                .as_deref()
+                .ok_or_else(|| invalid(&format!("missing `{name}`")))?;
+            URL_SAFE_NO_PAD
+                .decode(value)
+                .map(Zeroizing::new)
+                .map_err(|_| invalid(&format!("`{name}` is not base64url")))
+        };
+
+        let key = match (jwk.kty.as_str(), jwk.crv.as_deref()) {
+            ("RSA", _) => {
+                let uint = |value, name| param(value, name).map(|b| BigUint::from_bytes_be(&b));
+                let primes = vec![uint(&jwk.p, "p")?, uint(&jwk.q, "q")?];
+                let key = RsaPrivateKey::from_components(
+                    uint(&jwk.n, "n")?,
+                    uint(&jwk.e, "e")?,
+                    uint(&jwk.d, "d")?,
+                    primes,
+                )
+                .map_err(|err| invalid(&err.to_string()))?;
+                PrivateKey::Rsa(Box::new(key))
+            }
+            ("EC", Some("P-256")) => {
+                let d = param(&jwk.d, "d")?;
+                let key =
+                    p256::SecretKey::from_slice(&d).map_err(|_| invalid("invalid P-256 key"))?;
+                PrivateKey::P256(key)
+            }
+            ("OKP", Some("X25519")) => {
+                let d: Zeroizing<[u8; 32]> = Zeroizing::new(
+                    param(&jwk.d, "d")?
+                        .as_slice()
+                        .try_into()
+                        .map_err(|_| invalid("X25519 keys are 32 bytes"))?,
+                );
+                PrivateKey::X25519(x25519_dalek::StaticSecret::from(*d))
+            }
+            ("oct", _) => PrivateKey::Oct(param(&jwk.k, "k")?),
+            (kty, crv) => {
+                return Err(invalid(&format!(
+                    "unsupported key type {kty} {}",
+                    crv.unwrap_or_default()
+                )))
+            }
+        };
+
+        Ok(Self {
+            kid: jwk.kid,
+            alg: jwk.alg,
+            key,
+        })
+    }
+
+    fn supports(&self, alg: &str) -> bool {
+        if self.alg.as_deref().is_some_and(|own| own != alg) {
+            return false;
+        }
+        match &self.key {
+            PrivateKey::Rsa(_) => matches!(alg, "RSA-OAEP" | "RSA-OAEP-256"),
+            PrivateKey::P256(_) | PrivateKey::X25519(_) => {
+                matches!(alg, "ECDH-ES" | "ECDH-ES+A128KW" | "ECDH-ES+A256KW")
+            }
+            PrivateKey::Oct(k) => matches!((alg, k.len()), ("A128KW", 16) | ("A256KW", 32)),
+        }
+    }
+
+    /// Recovers the content encryption key of a token.
+    fn content_key(
+        &self,
+        header: &JweHeader,
+        encrypted_key: &[u8],
+        enc: ContentEncryption,
+    ) -> Result<Zeroizing<Vec<u8>>, JweError> {
+        match (header.alg.as_str(), &self.key) {
+            ("RSA-OAEP", PrivateKey::Rsa(key)) => key
+                .decrypt_blinded(
+                    &mut rand_core::OsRng,
+                    Oaep::new::<sha1::Sha1>(),
+                    encrypted_key,
+                )
+                .map(Zeroizing::new)
+                .map_err(|_| JweError::DecryptionFailed),
+            ("RSA-OAEP-256", PrivateKey::Rsa(key)) => key
+                .decrypt_blinded(&mut rand_core::OsRng, Oaep::new::<Sha256>(), encrypted_key)
+                .map(Zeroizing::new)
+                .map_err(|_| JweError::DecryptionFailed),
+            ("A128KW" | "A256KW", PrivateKey::Oct(kek)) => unwrap_key(kek, encrypted_key),
+            ("ECDH-ES", _) => {
+                if !encrypted_key.is_empty() {
+                    return Err(JweError::InvalidFormat(
+                        "ECDH-ES tokens have an empty encrypted key".to_string(),
+                    ));
+                }
+                self.agree(header, &header.enc, enc.key_len())
+            }
+            ("ECDH-ES+A128KW", _) => {
+                unwrap_key(&self.agree(header, &header.alg, 16)?, encrypted_key)
+            }
+            ("ECDH-ES+A256KW", _) => {
+                unwrap_key(&self.agree(header, &header.alg, 32)?, encrypted_key)
+            }
+            (alg, _) => Err(JweError::UnsupportedAlgorithm(alg.to_string())),
+        }
+    }
+
+    /// Performs ECDH with the ephemeral key of the token and derives a key of
+    /// `key_len` bytes (RFC 7518, section 4.6).
+    fn agree(
+        &self,
+        header: &JweHeader,
+        algorithm_id: &str,
+        key_len: usize,
+    ) -> Result<Zeroizing<Vec<u8>>, JweError> {
+        let epk = header
+            .epk
+            .as_ref()
+            .ok_or_else(|| JweError::InvalidFormat("missing `epk`".to_string()))?;
+        let decode = |value: &Option<String>| {
+            value
+                .as_deref()
+                .and_then(|value| URL_SAFE_NO_PAD.decode(value).ok())
+                .ok_or_else(|| JweError::InvalidFormat("invalid `epk`".to_string()))
+        };
+
+        let shared_secret = match (&self.key, epk.crv.as_deref()) {
+            (PrivateKey::P256(secret), Some("P-256")) => {
+                let point = [vec![0x04], decode(&epk.x)?, decode(&epk.y)?].concat();
+                let public = p256::PublicKey::from_sec1_bytes(&point)
+                    .map_err(|_| JweError::InvalidFormat("invalid `epk`".to_string()))?;
+                let shared =
+                    p256::ecdh::diffie_hellman(secret.to_nonzero_scalar(), public.as_affine());
+                Zeroizing::new(shared.raw_secret_bytes().to_vec())
+            }
+            (PrivateKey::X25519(secret), Some("X25519")) => {
+                let public: [u8; 32] = decode(&epk.x)?
+                    .try_into()
+                    .map_err(|_| JweError::InvalidFormat("invalid `epk`".to_string()))?;
+                let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(public));
+                // a low-order `epk` makes the shared secret known to anyone
+                if !shared.was_contributory() {
+                    return Err(JweError::InvalidFormat(
+                        "`epk` is a low-order point".to_string(),
+                    ));
+                }
+                Zeroizing::new(shared.as_bytes().to_vec())
+            }
+            _ => return Err(JweError::NoMatchingKey),
+        };
+
+        let apu = header
+            .apu
+            .as_deref()
+            .map(|apu| URL_SAFE_NO_PAD.decode(apu))
+            .transpose()
+            .map_err(|_| JweError::InvalidFormat("invalid `apu`".to_string()))?;
+        let apv = header
+            .apv
+            .as_deref()
+            .map(|apv| URL_SAFE_NO_PAD.decode(apv))
+            .transpose()
+            .map_err(|_| JweError::InvalidFormat("invalid `apv`".to_string()))?;
+        Ok(concat_kdf(
+            &shared_secret,
+            algorithm_id,
+            apu.as_deref().unwrap_or_default(),
+            apv.as_deref().unwrap_or_default(),
+            key_len,
+        ))
+    }
+}
+
+/// A set of private keys used to decrypt JWEs.
+#[derive(Clone, Default)]
+pub struct DecryptionJwks {
+    /// The decryption keys, in JWKS order.
+    pub keys: Vec<DecryptionJwk>,
+}
+
+impl DecryptionJwks {
+    /// Parses a private JWKS. Keys marked `use: sig`, and keys of an
+    /// unsupported type without a `use`, are skipped.
+    pub fn from_json(jwks: &[u8]) -> Result<Self, JweError> {
+        let jwks: serde_json::Value =
+            serde_json::from_slice(jwks).map_err(|err| JweError::InvalidKey {
+                key_id: None,
+                reason: err.to_string(),
+            })?;
+        let keys = jwks
+            .get("keys")
+            .and_then(serde_json::Value::as_array)
+            .ok_or_else(|| JweError::InvalidKey {
+                key_id: None,
+                reason: "missing `keys` array".to_string(),
+            })?
+            .iter()
+            .filter(
+                |key| match key.get("use").and_then(serde_json::Value::as_str) {
+                    Some("sig") => false,
+                    Some(_) => true,
+                    None => is_supported_key_type(key),
+                },
+            )
+            .map(DecryptionJwk::from_json)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { keys })
+    }
+
+    /// Decrypts a compact JWE and returns its plaintext.
+    ///
+    /// The key is chosen by the token's `kid`, or, without one, every key
+    /// supporting the token's `alg` is tried in turn.
+    pub fn decrypt(&self, token: &str) -> Result<Vec<u8>, JweError> {
+        let parts: Vec<&str> = token.split('.').collect();
+        let [protected, encrypted_key, iv, ciphertext, tag] = parts[..] else {
+            return Err(JweError::InvalidFormat(
+                "expected five dot-separated parts".to_string(),
+            ));
+        };
+        let decode = |part: &str, name: &str| {
+            URL_SAFE_NO_PAD
+                .decode(part)
+                .map_err(|_| JweError::InvalidFormat(format!("{name} is not base64url")))
+        };
+
+        let header: JweHeader = serde_json::from_slice(&decode(protected, "header")?)
+            .map_err(|err| JweError::InvalidFormat(err.to_string()))?;
+        if let Some(zip) = &header.zip {
+            return Err(JweError::UnsupportedAlgorithm(format!("zip {zip}")));
+        }
+        let enc = ContentEncryption::from_name(&header.enc)?;
+        let encrypted_key = decode(encrypted_key, "encrypted key")?;
+        let iv = decode(iv, "iv")?;
+        let ciphertext = decode(ciphertext, "ciphertext")?;
+        let tag = decode(tag, "tag")?;
+
+        let mut candidates = self
+            .keys
+            .iter()
+            .filter(|key| header.kid.is_none() || key.kid == header.kid)
+            .filter(|key| key.supports(&header.alg))
+            .peekable();
+        if candidates.peek().is_none() {
+            return Err(JweError::NoMatchingKey);
+        }
+
+        let mut error = JweError::DecryptionFailed;
+        for key in candidates {
+            let result = key
+                .content_key(&header, &encrypted_key, enc)
+                .and_then(|cek| enc.decrypt(&cek, &iv, &ciphertext, &tag, protected.as_bytes()));
+            match result {
+                Ok(plaintext) => return Ok(plaintext),
+                Err(err) => error = err,
+            }
+        }
+        Err(error)
+    }
+
+    /// Decrypts a compact JWE wrapping a signed JWT (a nested JWT) and
+    /// verifies the inner token with [`Jwks::decode`].
+    pub fn decrypt_and_decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        jwks: &Jwks,
+        validation: &Validation,
+    ) -> Result<TokenData<T>, JweError> {
+        let plaintext = self.decrypt(token)?;
+        let inner = std::str::from_utf8(&plaintext)
+            .map_err(|_| JweError::InvalidFormat("the payload is not a JWS".to_string()))?;
+        jwks.decode(inner.trim(), validation)
+            .map_err(JweError::InvalidInnerToken)
+    }
+}
+
+/// Whether `key` has a key type and curve [`DecryptionJwk`] can read.
+fn is_supported_key_type(key: &serde_json::Value) -> bool {
+    let member = |name| key.get(name).and_then(serde_json::Value::as_str);
+    matches!(
+        (member("kty"), member("crv")),
+        (Some("RSA" | "oct"), _) | (Some("EC"), Some("P-256")) | (Some("OKP"), Some("X25519"))
+    )
+}
+
+#[derive(Deserialize)]
+struct JweHeader {
+    alg: String,
+    enc: String,
+    kid: Option<String>,
+    zip: Option<String>,
+    epk: Option<EphemeralKey>,
+    apu: Option<String>,
+    apv: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EphemeralKey {
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+/// A content encryption algorithm (`enc`).
+#[derive(Clone, Copy)]
+enum ContentEncryption {
+    A128Gcm,
+    A256Gcm,
+    A128CbcHs256,
+    A256CbcHs512,
+}
+
+impl ContentEncryption {
+    fn from_name(enc: &str) -> Result<Self, JweError> {
+        match enc {
+            "A128GCM" => Ok(Self::A128Gcm),
+            "A256GCM" => Ok(Self::A256Gcm),
+            "A128CBC-HS256" => Ok(Self::A128CbcHs256),
+            "A256CBC-HS512" => Ok(Self::A256CbcHs512),
+            other => Err(JweError::UnsupportedAlgorithm(other.to_string())),
+        }
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            Self::A128Gcm => 16,
+            Self::A256Gcm | Self::A128CbcHs256 => 32,
+            Self::A256CbcHs512 => 64,
+        }
+    }
+
+    fn decrypt(
+        self,
+        cek: &[u8],
+        iv: &[u8],
+        ciphertext: &[u8],
+        tag: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, JweError> {
+        if cek.len() != self.key_len() {
+            return Err(JweError::DecryptionFailed);
+        }
+        match self {
+            Self::A128Gcm => gcm_decrypt::<Aes128Gcm>(cek, iv, ciphertext, tag, aad),
+            Self::A256Gcm => gcm_decrypt::<Aes256Gcm>(cek, iv, ciphertext, tag, aad),
+            Self::A128CbcHs256 => cbc_hmac_decrypt::<cbc::Decryptor<aes::Aes128>, Hmac<Sha256>>(
+                cek, iv, ciphertext, tag, aad,
+            ),
+            Self::A256CbcHs512 => cbc_hmac_decrypt::<cbc::Decryptor<aes::Aes256>, Hmac<Sha512>>(
+                cek, iv, ciphertext, tag, aad,
+            ),
+        }
+    }
+}
+
+fn gcm_decrypt<C: KeyInit + Aead>(
+    cek: &[u8],
+    iv: &[u8],
+    ciphertext: &[u8],
+    tag: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, JweError> {
+    if iv.len() != 12 {
+        return Err(JweError::DecryptionFailed);
+    }
+    let cipher = C::new_from_slice(cek).map_err(|_| JweError::DecryptionFailed)?;
+    let msg = [ciphertext, tag].concat();
+    cipher
+        .decrypt(Nonce::from_slice(iv), Payload { msg: &msg, aad })
+        .map_err(|_| JweError::DecryptionFailed)
+}
+
+/// AES-CBC with HMAC-SHA2 (RFC 7518, section 5.2): the first half of the key
+/// authenticates, the second half encrypts.
+fn cbc_hmac_decrypt<D: KeyIvInit + BlockDecryptMut, M: Mac + KeyInit>(
+    cek: &[u8],
+    iv: &[u8],
+    ciphertext: &[u8],
+    tag: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, JweError> {
+    let (mac_key, enc_key) = cek.split_at(cek.len() / 2);
+    let mut mac = <M as Mac>::new_from_slice(mac_key).map_err(|_| JweError::DecryptionFailed)?;
+    mac.update(aad);
+    mac.update(iv);
+    mac.update(ciphertext);
+    mac.update(&(aad.len() as u64 * 8).to_be_bytes());
+    if tag.len() != mac_key.len() {
+        return Err(JweError::DecryptionFailed);
+    }
+    mac.verify_truncated_left(tag)
+        .map_err(|_| JweError::DecryptionFailed)?;
+
+    D::new_from_slices(enc_key, iv)
+        .map_err(|_| JweError::DecryptionFailed)?
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .map_err(|_| JweError::DecryptionFailed)
+}
+
+/// Unwraps a content encryption key with AES Key Wrap (RFC 3394).
+fn unwrap_key(kek: &[u8], wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>, JweError> {
+    let mut cek = Zeroizing::new(vec![0; wrapped.len().saturating_sub(8)]);
+    let result = match kek.len() {
+        16 => aes_kw::KekAes128::new(kek.into()).unwrap(wrapped, &mut cek),
+        32 => aes_kw::KekAes256::new(kek.into()).unwrap(wrapped, &mut cek),
+        _ => return Err(JweError::DecryptionFailed),
+    };
+    result.map_err(|_| JweError::DecryptionFailed)?;
+    Ok(cek)
+}
+
+/// The Concat KDF of NIST SP 800-56A with SHA-256, as used by `ECDH-ES`.
+fn concat_kdf(
+    shared_secret: &[u8],
+    algorithm_id: &str,
+    apu: &[u8],
+    apv: &[u8],
+    key_len: usize,
+) -> Zeroizing<Vec<u8>> {
+    let with_len = |data: &[u8]| [&(data.len() as u32).to_be_bytes()[..], data].concat();
+    let other_info = [
+        with_len(algorithm_id.as_bytes()),
+        with_len(apu),
+        with_len(apv),
+        ((key_len * 8) as u32).to_be_bytes().to_vec(),
+    ]
+    .concat();
+
+    let mut key = Zeroizing::new(Vec::with_capacity(key_len + 32));
+    let mut counter: u32 = 1;
+    while key.len() < key_len {
+        let mut hasher = Sha256::new();
+        hasher.update(counter.to_be_bytes());
+        hasher.update(shared_secret);
+        hasher.update(&other_info);
+        key.extend_from_slice(&hasher.finalize());
+        counter += 1;
+    }
+    key.truncate(key_len);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::aead::AeadCore;
+    use rsa::traits::{PrivateKeyParts, PublicKeyParts};
+    use serde_json::json;
+
+    use super::*;
+
+    fn b64(bytes: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Builds an `A256GCM` compact JWE around `plaintext`.
+    fn encrypt(
+        header: serde_json::Value,
+        cek: &[u8],
+        encrypted_key: &[u8],
+        plaintext: &[u8],
+    ) -> String {
+        let protected = b64(header.to_string().as_bytes());
+        let cipher = Aes256Gcm::new_from_slice(cek).unwrap();
+        let iv = Aes256Gcm::generate_nonce(&mut rand_core::OsRng);
+        let sealed = cipher
+            .encrypt(
+                &iv,
+                Payload {
+                    msg: plaintext,
+                    aad: protected.as_bytes(),
+                },
+            )
+            .unwrap();
+        let (ciphertext, tag) = sealed.split_at(sealed.len() - 16);
+        format!(
+            "{protected}.{}.{}.{}.{}",
+            b64(encrypted_key),
+            b64(&iv),
+            b64(ciphertext),
+            b64(tag)
+        )
+    }
+
+    #[test]
+    fn decrypts_rfc7516_key_wrap_example() {
+        // RFC 7516, appendix A.3
+        let keys = DecryptionJwks::from_json(
+            json!({ "keys": [{ "kty": "oct", "k": "GawgguFyGrWKav7AX4VKUg" }] })
+                .to_string()
+                .as_bytes(),
+        )
+        .unwrap();
+        let token = "eyJhbGciOiJBMTI4S1ciLCJlbmMiOiJBMTI4Q0JDLUhTMjU2In0.\
+            6KB707dM9YTIgHtLvtgWQ8mKwboJW3of9locizkDTHzBC2IlrT1oOQ.\
+            AxY8DCtDaGlsbGljb3RoZQ.\
+            KDlTtXchhZTGufMYmOYGS4HffxPSUrfmqCHXaI9wOGY.\
+            U0m_YmjN04DJvceFICbCVQ";
+        assert_eq!(keys.decrypt(token).unwrap(), b"Live long and prosper.");
+
+        let tampered = token.replace("U0m_", "U1m_");
+        assert!(matches!(
+            keys.decrypt(&tampered),
+            Err(JweError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn derives_rfc7518_ecdh_es_key() {
+        // RFC 7518, appendix C
+        let bob = DecryptionJwk::from_json(&json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": "weNJy2HscCSM6AEDTDg04biOvhFhyyWvOHQfeF_PxMQ",
+            "y": "e8lnCO-AlStT-NJVX-crhB7QRYhiix03illJOVAOyck",
+            "d": "VEmDZpDXXK8p8N0Cndsxs924q6nS1RXFASRl6BfUqdw"
+        }))
+        .unwrap();
+        let header: JweHeader = serde_json::from_value(json!({
+            "alg": "ECDH-ES",
+            "enc": "A128GCM",
+            "apu": "QWxpY2U",
+            "apv": "Qm9i",
+            "epk": {
+                "kty": "EC",
+                "crv": "P-256",
+                "x": "gI0GAILBdu7T53akrFmMyGcsF3n5dO7MmwNBHKW5SV0",
+                "y": "SLW_xSffzlPWrHEVI30DHM_4egVwt3NQqeUD7nMFpps"
+            }
+        }))
+        .unwrap();
+
+        let key = bob.agree(&header, "A128GCM", 16).unwrap();
+        assert_eq!(b64(&key), "VqqN6vgjbSBcIijNcacQGg");
+    }
+
+    #[test]
+    fn decrypts_rsa_oaep_token() {
+        let private_key = RsaPrivateKey::new(&mut rand_core::OsRng, 2048).unwrap();
+        let uint = |n: &BigUint| b64(&n.to_bytes_be());
+        let keys = DecryptionJwks::from_json(
+            json!({ "keys": [
+                { "kty": "oct", "use": "sig", "k": "c2lnbmluZw" },
+                {
+                    "kty": "RSA",
+                    "use": "enc",
+                    "kid": "rsa-enc",
+                    "n": uint(private_key.n()),
+                    "e": uint(private_key.e()),
+                    "d": uint(private_key.d()),
+                    "p": uint(&private_key.primes()[0]),
+                    "q": uint(&private_key.primes()[1]),
+                },
+            ]})
+            .to_string()
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(keys.keys.len(), 1);
+
+        let cek = [7u8; 32];
+        let encrypted_key = private_key
+            .to_public_key()
+            .encrypt(&mut rand_core::OsRng, Oaep::new::<Sha256>(), &cek)
+            .unwrap();
+        let header = json!({ "alg": "RSA-OAEP-256", "enc": "A256GCM", "kid": "rsa-enc" });
+        let token = encrypt(header, &cek, &encrypted_key, b"secret");
+        assert_eq!(keys.decrypt(&token).unwrap(), b"secret");
+
+        let header = json!({ "alg": "RSA-OAEP-256", "enc": "A256GCM", "kid": "other" });
+        let token = encrypt(header, &cek, &encrypted_key, b"secret");
+        assert!(matches!(keys.decrypt(&token), Err(JweError::NoMatchingKey)));
+    }
+
+    #[test]
+    fn decrypts_nested_jwt_with_x25519_key_agreement() {
+        use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+
+        let recipient = x25519_dalek::StaticSecret::from([3u8; 32]);
+        let keys = DecryptionJwks::from_json(
+            json!({ "keys": [{ "kty": "OKP", "crv": "X25519", "d": b64(recipient.as_bytes()) }] })
+                .to_string()
+                .as_bytes(),
+        )
+        .unwrap();
+
+        // sender side: agree on a key-encryption key and wrap a fresh CEK
+        let ephemeral = x25519_dalek::StaticSecret::from([9u8; 32]);
+        let shared = ephemeral.diffie_hellman(&x25519_dalek::PublicKey::from(&recipient));
+        let kek = concat_kdf(shared.as_bytes(), "ECDH-ES+A256KW", b"", b"", 32);
+        let cek = [5u8; 32];
+        let mut wrapped = [0u8; 40];
+        aes_kw::KekAes256::new(kek.as_slice().into())
+            .wrap(&cek, &mut wrapped)
+            .unwrap();
+
+        let signing_secret = b"nested-signing-secret";
+        let claims = json!({ "sub": "nested", "exp": jsonwebtoken::get_current_timestamp() + 60 });
+        let mut inner_header = Header::new(Algorithm::HS256);
+        inner_header.kid = Some("hmac".to_string());
+        let inner = encode(
+            &inner_header,
+            &claims,
+            &EncodingKey::from_secret(signing_secret),
+        )
+        .unwrap();
+
+        let header = json!({
+            "alg": "ECDH-ES+A256KW",
+            "enc": "A256GCM",
+            "cty": "JWT",
+            "epk": {
+                "kty": "OKP",
+                "crv": "X25519",
+                "x": b64(x25519_dalek::PublicKey::from(&ephemeral).as_bytes())
+            }
+        });
+        let token = encrypt(header, &cek, &wrapped, inner.as_bytes());
+
+        let verification_keys = Jwks::from_jwk_set(
+            serde_json::from_value(json!({ "keys": [{
+                "kty": "oct",
+                "alg": "HS256",
+                "kid": "hmac",
+                "k": b64(signing_secret)
+            }]}))
+            .unwrap(),
+        )
+        .unwrap();
+        let data = keys
+            .decrypt_and_decode::<serde_json::Value>(
+                &token,
+                &verification_keys,
+                &Validation::new(Algorithm::HS256),
+            )
+            .unwrap();
+        assert_eq!(data.claims["sub"], "nested");
+    }
+
+    #[test]
+    fn rejects_low_order_x25519_epk() {
+        let keys = DecryptionJwks::from_json(
+            json!({ "keys": [{ "kty": "OKP", "crv": "X25519", "d": b64(&[3u8; 32]) }] })
+                .to_string()
+                .as_bytes(),
+        )
+        .unwrap();
+        let header: JweHeader = serde_json::from_value(json!({
+            "alg": "ECDH-ES",
+            "enc": "A256GCM",
+            "epk": { "kty": "OKP", "crv": "X25519", "x": b64(&[0u8; 32]) }
+        }))
+        .unwrap();
+        assert!(matches!(
+            keys.keys[0].agree(&header, "A256GCM", 32),
+            Err(JweError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn skips_unsupported_keys_without_use() {
+        let ed25519 = json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo",
+            "d": "nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A"
+        });
+        let x25519 = json!({ "kty": "OKP", "crv": "X25519", "d": b64(&[3u8; 32]) });
+
+        let keys =
+            DecryptionJwks::from_json(json!({ "keys": [ed25519, x25519] }).to_string().as_bytes())
+                .unwrap();
+        assert_eq!(keys.keys.len(), 1);
+
+        // a key declared for encryption must be usable for it
+        let mut ed25519 = ed25519;
+        ed25519["use"] = json!("enc");
+        assert!(matches!(
+            DecryptionJwks::from_json(json!({ "keys": [ed25519] }).to_string().as_bytes()),
+            Err(JweError::InvalidKey { .. })
+        ));
+    }
+}
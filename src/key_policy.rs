@@ -0,0 +1,394 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk;
+
+use crate::JwkError;
+#[cfg(feature = "x509")]
+use crate::X5cPolicy;
+
+/// The `kty` of a JSON Web Key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    /// `RSA`
+    Rsa,
+    /// `EC`
+    Ec,
+    /// `OKP`
+    Okp,
+    /// `oct`, a symmetric key.
+    Oct,
+}
+
+impl KeyType {
+    pub(crate) fn of(params: &jwk::AlgorithmParameters) -> Self {
+        match params {
+            jwk::AlgorithmParameters::RSA(_) => KeyType::Rsa,
+            jwk::AlgorithmParameters::EllipticCurve(_) => KeyType::Ec,
+            jwk::AlgorithmParameters::OctetKeyPair(_) => KeyType::Okp,
+            jwk::AlgorithmParameters::OctetKey(_) => KeyType::Oct,
+        }
+    }
+}
+
+/// What to do when several keys in a JWKS share a `kid`, as happens during
+/// some providers' key rotations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateKids {
+    /// Fail with [`JwksError::DuplicateKeyId`][crate::JwksError::DuplicateKeyId].
+    Error,
+    /// Keep the first key and ignore later ones.
+    KeepFirst,
+    /// Keep every key. The first one is returned by
+    /// [`Jwks::get`][crate::Jwks::get], and the key used to verify a token
+    /// is chosen by the token's `alg`.
+    #[default]
+    KeepAll,
+}
+
+/// A key left out of a [`Jwks`][crate::Jwks] because it violates the
+/// [`KeyPolicy`] or uses an unsupported curve.
+#[derive(Clone, Debug)]
+pub struct RejectedKey {
+    /// The position of the key in the JWKS `keys` array.
+    pub index: usize,
+    /// The `kid` of the key, if it has one.
+    pub kid: Option<String>,
+    /// Why the key was left out.
+    pub error: JwkError,
+}
+
+/// Rules applied to every key while parsing a JWKS.
+///
+/// A key that violates the policy is left out of the key set and listed in
+/// [`Jwks::rejected`][crate::Jwks::rejected], so one weak key does not stop
+/// the others from verifying tokens. Set
+/// [`fail_on_violation`][Self::fail_on_violation] to fail the whole set
+/// instead.
+///
+/// The default policy accepts every key that can be turned into a
+/// [`DecodingKey`][jsonwebtoken::DecodingKey], whatever its size: it is what
+/// [`Jwks::from_jwk_set`][crate::Jwks::from_jwk_set],
+/// [`JwkEntry::from_jsonwebkey_ref`][crate::JwkEntry::from_jsonwebkey_ref]
+/// and `Jwks::from_iter` apply. Only fetched key sets default to
+/// [`KeyPolicy::remote`], which refuses RSA keys shorter than 2048 bits and
+/// symmetric keys; pass it to
+/// [`Jwks::from_jwk_set_with_policy`][crate::Jwks::from_jwk_set_with_policy]
+/// to hold local key sets to the same minimum.
+///
+/// Build a policy from [`KeyPolicy::default`] or [`KeyPolicy::remote`] with
+/// the `with_*` methods: more fields may be added, e.g. by crate features.
+///
+/// ```
+/// # use jwks::{KeyPolicy, KeyType};
+/// let policy = KeyPolicy::remote()
+///     .with_min_rsa_bits(3072)
+///     .with_allowed_key_types([KeyType::Rsa, KeyType::Ec]);
+/// ```
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct KeyPolicy {
+    /// The minimum RSA modulus length in bits. Defaults to 0, and to 2048 in
+    /// [`KeyPolicy::remote`].
+    pub min_rsa_bits: usize,
+    /// The key types to accept, or `None` to accept all of them.
+    pub allowed_key_types: Option<Vec<KeyType>>,
+    /// The curves to accept for `EC` and `OKP` keys, or `None` to accept all
+    /// of them.
+    pub allowed_curves: Option<Vec<jwk::EllipticCurve>>,
+    /// Accept symmetric `oct` keys. Defaults to `true`, and to `false` in
+    /// [`KeyPolicy::remote`].
+    ///
+    /// An `oct` key in a fetched JWKS lets anyone able to tamper with the
+    /// response mint HMAC-signed tokens, so only enable this for remote key
+    /// sets you trust as much as the secret itself.
+    pub allow_symmetric: bool,
+    /// How to handle keys sharing a `kid`. Defaults to
+    /// [`DuplicateKids::KeepAll`].
+    pub duplicate_kids: DuplicateKids,
+    /// Fail the whole key set when a key violates the policy or uses an
+    /// unsupported curve, instead of leaving the key out and listing it in
+    /// [`Jwks::rejected`][crate::Jwks::rejected]. Defaults to `false`.
+    pub fail_on_violation: bool,
+    /// Validate the `x5c` certificate chain of each key.
+    #[cfg(feature = "x509")]
+    pub x5c: Option<X5cPolicy>,
+}
+
+impl Default for KeyPolicy {
+    fn default() -> Self {
+        Self {
+            min_rsa_bits: 0,
+            allowed_key_types: None,
+            allowed_curves: None,
+            allow_symmetric: true,
+            duplicate_kids: DuplicateKids::KeepAll,
+            fail_on_violation: false,
+            #[cfg(feature = "x509")]
+            x5c: None,
+        }
+    }
+}
+
+impl KeyPolicy {
+    /// The policy used for fetched key sets: the default policy, but refusing
+    /// RSA keys shorter than 2048 bits and symmetric `oct` keys.
+    pub fn remote() -> Self {
+        Self {
+            min_rsa_bits: 2048,
+            allow_symmetric: false,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the minimum RSA modulus length in bits.
+    pub fn with_min_rsa_bits(mut self, min_rsa_bits: usize) -> Self {
+        self.min_rsa_bits = min_rsa_bits;
+        self
+    }
+
+    /// Only accepts keys of the given types.
+    pub fn with_allowed_key_types(mut self, key_types: impl IntoIterator<Item = KeyType>) -> Self {
+        self.allowed_key_types = Some(key_types.into_iter().collect());
+        self
+    }
+
+    /// Only accepts `EC` and `OKP` keys on the given curves.
+    pub fn with_allowed_curves(
+        mut self,
+        curves: impl IntoIterator<Item = jwk::EllipticCurve>,
+    ) -> Self {
+        self.allowed_curves = Some(curves.into_iter().collect());
+        self
+    }
+
+    /// Sets whether symmetric `oct` keys are accepted.
+    pub fn with_allow_symmetric(mut self, allow_symmetric: bool) -> Self {
+        self.allow_symmetric = allow_symmetric;
+        self
+    }
+
+    /// Sets how keys sharing a `kid` are handled.
+    pub fn with_duplicate_kids(mut self, duplicate_kids: DuplicateKids) -> Self {
+        self.duplicate_kids = duplicate_kids;
+        self
+    }
+
+    /// Sets whether a key violating the policy fails the whole key set.
+    pub fn with_fail_on_violation(mut self, fail_on_violation: bool) -> Self {
+        self.fail_on_violation = fail_on_violation;
+        self
+    }
+
+    /// Validates the `x5c` certificate chain of each key against `x5c`.
+    #[cfg(feature = "x509")]
+    pub fn with_x5c(mut self, x5c: X5cPolicy) -> Self {
+        self.x5c = Some(x5c);
+        self
+    }
+
+    /// Checks `jwk`, identified by `key_id`, against this policy.
+    pub(crate) fn check(&self, key_id: &str, jwk: &jwk::Jwk) -> Result<(), JwkError> {
+        let key_type = KeyType::of(&jwk.algorithm);
+        let type_allowed = self
+            .allowed_key_types
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(&key_type));
+        if !type_allowed || (key_type == KeyType::Oct && !self.allow_symmetric) {
+            return Err(JwkError::DisallowedKeyType {
+                key_id: key_id.to_string(),
+                key_type,
+            });
+        }
+
+        let curve = match &jwk.algorithm {
+            jwk::AlgorithmParameters::EllipticCurve(params) => Some(&params.curve),
+            jwk::AlgorithmParameters::OctetKeyPair(params) => Some(&params.curve),
+            _ => None,
+        };
+        if let (Some(curve), Some(allowed)) = (curve, &self.allowed_curves) {
+            if !allowed.contains(curve) {
+                return Err(JwkError::DisallowedCurve {
+                    key_id: key_id.to_string(),
+                    curve: curve.clone(),
+                });
+            }
+        }
+
+        if let jwk::AlgorithmParameters::RSA(params) = &jwk.algorithm {
+            // an undecodable modulus is reported when building the decoding key
+            if let Some(bits) = modulus_bits(&params.n) {
+                if bits < self.min_rsa_bits {
+                    return Err(JwkError::KeyTooSmall {
+                        key_id: key_id.to_string(),
+                        bits,
+                        min_bits: self.min_rsa_bits,
+                    });
+                }
+            }
+        }
+
+        #[cfg(feature = "x509")]
+        if let Some(x5c) = &self.x5c {
+            x5c.validate(jwk).map_err(|error| JwkError::X5cError {
+                key_id: key_id.to_string(),
+                error,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn modulus_bits(n: &str) -> Option<usize> {
+    let n = URL_SAFE_NO_PAD.decode(n).ok()?;
+    let first = n.iter().position(|b| *b != 0).unwrap_or(n.len());
+    let n = &n[first..];
+    Some(
+        n.first()
+            .map_or(0, |b| n.len() * 8 - b.leading_zeros() as usize),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn jwk(value: serde_json::Value) -> jwk::Jwk {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn rejects_short_rsa_modulus() {
+        let mut n = vec![0u8; 128];
+        n[1] = 0x80;
+        let key = jwk(json!({
+            "kty": "RSA",
+            "n": URL_SAFE_NO_PAD.encode(&n),
+            "e": "AQAB",
+        }));
+
+        assert!(matches!(
+            KeyPolicy::remote().check("short", &key),
+            Err(JwkError::KeyTooSmall {
+                bits: 1016,
+                min_bits: 2048,
+                ..
+            })
+        ));
+        let lenient = KeyPolicy::remote().with_min_rsa_bits(1000);
+        lenient.check("short", &key).unwrap();
+        KeyPolicy::default().check("short", &key).unwrap();
+    }
+
+    #[test]
+    fn local_key_sets_accept_short_rsa_keys() {
+        use crate::{JwkEntry, Jwks};
+
+        let mut n = vec![0u8; 128];
+        n[0] = 0x80;
+        let key = jwk(json!({
+            "kty": "RSA",
+            "kid": "legacy",
+            "n": URL_SAFE_NO_PAD.encode(&n),
+            "e": "AQAB",
+        }));
+
+        JwkEntry::from_jsonwebkey_ref(&key).unwrap();
+        let jwks = Jwks::from_jwk_set(jwk::JwkSet { keys: vec![key] }).unwrap();
+        assert!(jwks.get("legacy").is_some());
+        assert!(jwks.rejected().is_empty());
+    }
+
+    #[test]
+    fn restricts_key_types_and_curves() {
+        let ec = jwk(json!({ "kty": "EC", "crv": "P-384", "x": "", "y": "" }));
+        let oct = jwk(json!({ "kty": "oct", "k": "GawgguFyGrWKav7AX4VKUg" }));
+
+        let policy = KeyPolicy {
+            allowed_curves: Some(vec![jwk::EllipticCurve::P256]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            policy.check("ec", &ec),
+            Err(JwkError::DisallowedCurve {
+                curve: jwk::EllipticCurve::P384,
+                ..
+            })
+        ));
+        policy.check("oct", &oct).unwrap();
+
+        let policy = KeyPolicy {
+            allow_symmetric: false,
+            ..Default::default()
+        };
+        assert!(matches!(
+            policy.check("oct", &oct),
+            Err(JwkError::DisallowedKeyType {
+                key_type: KeyType::Oct,
+                ..
+            })
+        ));
+
+        let policy = KeyPolicy {
+            allowed_key_types: Some(vec![KeyType::Rsa]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            policy.check("ec", &ec),
+            Err(JwkError::DisallowedKeyType {
+                key_type: KeyType::Ec,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn leaves_out_keys_violating_the_policy() {
+        use crate::{Jwks, JwksError};
+
+        let mut n = vec![0u8; 128];
+        n[0] = 0x80;
+        let jwk_set = jwk::JwkSet {
+            keys: vec![
+                jwk(json!({
+                    "kty": "RSA",
+                    "kid": "weak",
+                    "n": URL_SAFE_NO_PAD.encode(&n),
+                    "e": "AQAB",
+                })),
+                jwk(json!({
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "kid": "strong",
+                    "x": "tOOp1Sko9BGCpfjK-mAOQF875W-xdWPnHbNtjs0xv3I",
+                    "y": "atwZ-tOsAFi6rQ1DOHzu3x6K6t8jLGAAtxwG8cIaJks",
+                })),
+            ],
+        };
+        let policy = KeyPolicy {
+            min_rsa_bits: 2048,
+            ..Default::default()
+        };
+
+        let jwks = Jwks::from_jwk_set_with_policy(jwk_set.clone(), &policy).unwrap();
+        assert!(jwks.get("strong").is_some());
+        assert!(jwks.get("weak").is_none());
+        let rejected = jwks.rejected();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].index, 0);
+        assert_eq!(rejected[0].kid.as_deref(), Some("weak"));
+        assert!(matches!(
+            rejected[0].error,
+            JwkError::KeyTooSmall { bits: 1024, .. }
+        ));
+
+        let policy = KeyPolicy {
+            fail_on_violation: true,
+            ..policy
+        };
+        assert!(matches!(
+            Jwks::from_jwk_set_with_policy(jwk_set, &policy),
+            Err(JwksError::KeyError(JwkError::KeyTooSmall { .. }))
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{jwk, Algorithm};
+use rand_core::{OsRng, RngCore};
+use rsa::{traits::PrivateKeyParts, traits::PublicKeyParts, BigUint, RsaPrivateKey};
+use serde_json::json;
+
+use crate::{thumbprint::thumbprint, SigningJwk, SigningKeyError};
+
+/// Generates fresh key pairs for tests and local development.
+///
+/// The key type follows `alg`: RSA for `RS*` and `PS*`, P-256 or P-384 for
+/// `ES256` and `ES384`, Ed25519 for `EdDSA`, and a random secret as long as
+/// the hash for `HS*`.
+#[derive(Clone, Debug)]
+pub struct KeyGenerator {
+    /// The algorithm the key is generated for.
+    pub alg: Algorithm,
+    /// The `kid` of the key, or `None` to use its RFC 7638 thumbprint.
+    ///
+    /// The thumbprint of an `oct` key is a hash of the secret, so `HS*` keys
+    /// get a random `kid` instead.
+    pub kid: Option<String>,
+    /// The RSA modulus length in bits. Defaults to 2048.
+    pub rsa_bits: usize,
+}
+
+/// A generated key pair.
+#[derive(Clone)]
+pub struct GeneratedKey {
+    /// The private JWK, e.g. to store in a secrets manager.
+    pub private_jwk: serde_json::Value,
+    /// The key to sign tokens with.
+    pub signing_key: SigningJwk,
+}
+
+impl KeyGenerator {
+    /// A generator for `alg` keys identified by their thumbprint, or by a
+    /// random `kid` for `HS*` keys.
+    pub fn new(alg: Algorithm) -> Self {
+        Self {
+            alg,
+            kid: None,
+            rsa_bits: 2048,
+        }
+    }
+
+    /// Sets the `kid` of the generated keys.
+    pub fn with_kid(mut self, kid: impl Into<String>) -> Self {
+        self.kid = Some(kid.into());
+        self
+    }
+
+    /// Generates a key pair.
+    pub fn generate(&self) -> Result<GeneratedKey, SigningKeyError> {
+        let mut private_jwk = match self.alg {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => {
+                let key = RsaPrivateKey::new(&mut OsRng, self.rsa_bits).map_err(|err| {
+                    SigningKeyError::UnsupportedKey(format!("{}-bit RSA: {err}", self.rsa_bits))
+                })?;
+                let uint = |n: &BigUint| URL_SAFE_NO_PAD.encode(n.to_bytes_be());
+                json!({
+                    "kty": "RSA",
+                    "n": uint(key.n()),
+                    "e": uint(key.e()),
+                    "d": uint(key.d()),
+                    "p": uint(&key.primes()[0]),
+                    "q": uint(&key.primes()[1]),
+                })
+            }
+            Algorithm::ES256 => json!({
+                "kty": "EC",
+                "crv": "P-256",
+                "d": URL_SAFE_NO_PAD.encode(p256::SecretKey::random(&mut OsRng).to_bytes()),
+            }),
+            Algorithm::ES384 => json!({
+                "kty": "EC",
+                "crv": "P-384",
+                "d": URL_SAFE_NO_PAD.encode(p384::SecretKey::random(&mut OsRng).to_bytes()),
+            }),
+            Algorithm::EdDSA => json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "d": URL_SAFE_NO_PAD.encode(random_bytes(32)),
+            }),
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let len = match self.alg {
+                    Algorithm::HS256 => 32,
+                    Algorithm::HS384 => 48,
+                    _ => 64,
+                };
+                json!({ "kty": "oct", "k": URL_SAFE_NO_PAD.encode(random_bytes(len)) })
+            }
+        };
+        private_jwk["alg"] = json!(self.alg);
+
+        // fill in the public members, so the private JWK is complete
+        let public = SigningJwk::from_json(&private_jwk)?.verifying_jwk().clone();
+        for (name, value) in serde_json::to_value(&public.algorithm)
+            .expect("JWK parameters serialize")
+            .as_object()
+            .into_iter()
+            .flatten()
+        {
+            private_jwk
+                .as_object_mut()
+                .expect("private JWKs are objects")
+                .entry(name)
+                .or_insert_with(|| value.clone());
+        }
+        let kid = match (&self.kid, &public.algorithm) {
+            (Some(kid), _) => kid.clone(),
+            // the thumbprint would let anyone confirm a guess of the secret
+            (None, jwk::AlgorithmParameters::OctetKey(_)) => {
+                URL_SAFE_NO_PAD.encode(random_bytes(16))
+            }
+            (None, params) => thumbprint(params),
+        };
+        private_jwk["kid"] = json!(kid);
+
+        Ok(GeneratedKey {
+            signing_key: SigningJwk::from_json(&private_jwk)?,
+            private_jwk,
+        })
+    }
+}
+
+impl GeneratedKey {
+    /// The public JWK to publish, or `None` for `oct` keys.
+    pub fn public_jwk(&self) -> Option<&jwk::Jwk> {
+        self.signing_key.public_jwk()
+    }
+
+    /// The JWK verifiers need: the public JWK, or for `oct` keys the secret.
+    pub fn verifying_jwk(&self) -> &jwk::Jwk {
+        self.signing_key.verifying_jwk()
+    }
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::Validation;
+
+    use super::*;
+    use crate::Jwks;
+
+    #[test]
+    fn generates_keys_for_each_algorithm() {
+        let claims =
+            json!({ "sub": "generated", "exp": jsonwebtoken::get_current_timestamp() + 60 });
+        for alg in [
+            Algorithm::RS256,
+            Algorithm::ES256,
+            Algorithm::ES384,
+            Algorithm::EdDSA,
+            Algorithm::HS256,
+        ] {
+            let key = KeyGenerator::new(alg).generate().unwrap();
+            let kid = key.signing_key.kid.clone().unwrap();
+            if alg == Algorithm::HS256 {
+                assert_ne!(kid, thumbprint(&key.verifying_jwk().algorithm));
+                assert_eq!(URL_SAFE_NO_PAD.decode(&kid).unwrap().len(), 16);
+            } else {
+                assert_eq!(kid, thumbprint(&key.verifying_jwk().algorithm));
+            }
+            assert_eq!(key.public_jwk().is_none(), alg == Algorithm::HS256);
+            assert_eq!(key.private_jwk["alg"], json!(alg));
+
+            let token = key.signing_key.sign(&claims).unwrap();
+            let jwks = Jwks::from_jwk_set(jwk::JwkSet {
+                keys: vec![key.verifying_jwk().clone()],
+            })
+            .unwrap();
+            let data = jwks
+                .decode::<serde_json::Value>(&token, &Validation::new(alg))
+                .unwrap();
+            assert_eq!(data.header.kid.as_deref(), Some(kid.as_str()));
+        }
+
+        let key = KeyGenerator::new(Algorithm::ES256)
+            .with_kid("chosen")
+            .generate()
+            .unwrap();
+        assert_eq!(
+            key.public_jwk().unwrap().common.key_id.as_deref(),
+            Some("chosen")
+        );
+        assert_eq!(key.private_jwk["kid"], "chosen");
+    }
+}
@@ -1,86 +1,288 @@
 // Mostly edit from https://github.com/cdriehuys/axum-jwks/blob/main/axum-jwks/src/jwks.rs
 
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
 
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use jsonwebtoken::{
     jwk::{self, KeyAlgorithm},
-    DecodingKey,
+    Algorithm, DecodingKey,
 };
-use serde::Deserialize;
 use thiserror::Error;
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "http")]
+mod cache;
+mod diff;
+#[cfg(feature = "http")]
+mod fetcher;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "jwe")]
+mod jwe;
+mod key_policy;
+#[cfg(feature = "keygen")]
+mod keygen;
+#[cfg(feature = "pem")]
+mod pem;
+#[cfg(feature = "http")]
+mod policy;
+#[cfg(feature = "http")]
+mod refresh;
+#[cfg(feature = "signing")]
+mod signing;
+#[cfg(feature = "http")]
+mod store;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "thumbprint")]
+mod thumbprint;
+mod verify;
+#[cfg(feature = "x509")]
+mod x509;
+
+#[cfg(feature = "http")]
+pub use cache::DiskCache;
+pub use diff::{ChangedKey, KeyRef, KeySetDiff, MergeConflict};
+#[cfg(feature = "http")]
+pub use fetcher::{FetchResponse, JwksFetcher};
+#[cfg(feature = "jwe")]
+pub use jwe::{DecryptionJwk, DecryptionJwks, JweError};
+pub use key_policy::{DuplicateKids, KeyPolicy, KeyType, RejectedKey};
+#[cfg(feature = "keygen")]
+pub use keygen::{GeneratedKey, KeyGenerator};
+#[cfg(feature = "pem")]
+pub use pem::PemError;
+#[cfg(feature = "http")]
+pub use policy::{FetchPolicy, JwksUriHosts};
+#[cfg(feature = "http")]
+pub use refresh::{RefreshHealth, RefreshingJwks, RetiringKey};
+#[cfg(feature = "signing")]
+pub use signing::{SigningJwk, SigningKeyError};
+#[cfg(feature = "http")]
+pub use store::{JwksStore, MemoryStore, StoredJwks};
+pub use verify::VerifyError;
+#[cfg(feature = "x509")]
+pub use x509::{X5cError, X5cPolicy};
+
+/// The error type returned by a fetcher.
+pub type FetchError = Box<dyn std::error::Error + Send + Sync>;
+
 /// A container for a set of JWT decoding keys.
 ///
 /// The container can be used to validate any JWT that identifies a known key
-/// through the `kid` attribute in the token's header.
+/// through the `kid` attribute in the token's header. Look keys up with the
+/// query methods ([`get`][Self::get], [`get_for`][Self::get_for],
+/// [`iter`][Self::iter], ...); the deprecated `keys` map only holds one key
+/// per `kid`.
 #[derive(Clone)]
 #[allow(dead_code)]
 pub struct Jwks {
+    #[deprecated(note = "holds only one key per `kid`; use `get`, `get_for` or `iter` instead")]
     pub keys: HashMap<String, Jwk>,
+    /// Keys sharing their `kid` with a key in `keys`, kept under
+    /// [`DuplicateKids::KeepAll`].
+    duplicates: Vec<(String, Jwk)>,
+    /// When the set was loaded from a cache because fetching failed, the
+    /// time its keys were fetched.
+    stale_since: Option<u64>,
+    /// The keys left out because they violate the [`KeyPolicy`].
+    rejected: Vec<RejectedKey>,
 }
 
-#[derive(Deserialize)]
-struct OIDCConfig {
-    jwks_uri: String,
-}
-
+// the set's own methods still maintain the deprecated `keys` map
+#[allow(deprecated)]
 impl Jwks {
-    /// # Arguments
-    /// * `oidc_url` - The url with OpenID configuration, e.g. https://accounts.google.com/.well-known/openid-configuration
-    pub async fn from_oidc_url(oidc_url: impl Into<String>) -> Result<Self, JwksError> {
-        let url_str = oidc_url.into();
-        Self::validate_url_scheme(&url_str)?;
-        Self::from_oidc_url_with_client(&reqwest::Client::default(), url_str).await
-    }
-
-    /// A version of [`from_oidc_url`][Self::from_oidc_url] that allows for
-    /// passing in a custom [`Client`][reqwest::Client].
-    pub async fn from_oidc_url_with_client(
-        client: &reqwest::Client,
-        oidc_url: impl Into<String>,
-    ) -> Result<Self, JwksError> {
-        let oidc_config = client
-            .get(oidc_url.into())
-            .send()
-            .await?
-            .json::<OIDCConfig>()
-            .await?;
-        let jwks_uri = oidc_config.jwks_uri;
-
-        Self::from_jwks_url_with_client(client, &jwks_uri).await
-    }
-
-    /// # Arguments
-    /// * `jwks_url` - The url which JWKS info is pulled from, e.g. https://www.googleapis.com/oauth2/v3/certs
-    pub async fn from_jwks_url(jwks_url: impl Into<String>) -> Result<Self, JwksError> {
-        let url_str = jwks_url.into();
-        Self::validate_url_scheme(&url_str)?;
-        Self::from_jwks_url_with_client(&reqwest::Client::default(), url_str).await
-    }
-
-    /// A version of [`from_jwks_url`][Self::from_jwks_url] that allows for
-    /// passing in a custom [`Client`][reqwest::Client].
-    pub async fn from_jwks_url_with_client(
-        client: &reqwest::Client,
-        jwks_url: impl Into<String>,
+    /// Builds a key set from an already parsed [`JwkSet`][jwk::JwkSet], e.g.
+    /// one loaded from a file or embedded in configuration.
+    pub fn from_jwk_set(jwks: jwk::JwkSet) -> Result<Self, JwksError> {
+        Self::from_jwk_set_with_policy(jwks, &KeyPolicy::default())
+    }
+
+    /// A version of [`from_jwk_set`][Self::from_jwk_set] that checks every
+    /// key against a [`KeyPolicy`].
+    pub fn from_jwk_set_with_policy(
+        jwks: jwk::JwkSet,
+        policy: &KeyPolicy,
     ) -> Result<Self, JwksError> {
-        let jwks: jwk::JwkSet = client.get(jwks_url.into()).send().await?.json().await?;
+        Self::from_indexed_keys(jwks.keys.iter().enumerate(), Vec::new(), policy)
+    }
 
+    /// Builds a key set from keys and their positions in the JWKS, adding the
+    /// keys already left out while reading it to `rejected`.
+    pub(crate) fn from_indexed_keys<'a>(
+        jwks: impl IntoIterator<Item = (usize, &'a jwk::Jwk)>,
+        mut rejected: Vec<RejectedKey>,
+        policy: &KeyPolicy,
+    ) -> Result<Self, JwksError> {
         let mut keys = HashMap::new();
-        for jwk in jwks.keys {
-            let JwkEntry { kid, jwk } = jwk.try_into()?;
-            keys.insert(kid, jwk);
+        let mut duplicates = Vec::new();
+        for (index, jwk) in jwks {
+            let JwkEntry { kid, jwk } = match JwkEntry::from_jsonwebkey_with_policy(jwk, policy) {
+                Ok(entry) => entry,
+                Err(error) if error.is_skippable() && !policy.fail_on_violation => {
+                    rejected.push(RejectedKey {
+                        index,
+                        kid: jwk.common.key_id.clone(),
+                        error,
+                    });
+                    continue;
+                }
+                Err(error) => return Err(error.into()),
+            };
+            match keys.entry(kid) {
+                Entry::Vacant(entry) => {
+                    entry.insert(jwk);
+                }
+                Entry::Occupied(entry) => match policy.duplicate_kids {
+                    DuplicateKids::Error => {
+                        return Err(JwksError::DuplicateKeyId(entry.key().clone()))
+                    }
+                    DuplicateKids::KeepFirst => {}
+                    DuplicateKids::KeepAll => duplicates.push((entry.key().clone(), jwk)),
+                },
+            }
         }
 
-        Ok(Self { keys })
+        rejected.sort_by_key(|rejected| rejected.index);
+        Ok(Self {
+            keys,
+            duplicates,
+            stale_since: None,
+            rejected,
+        })
+    }
+
+    /// Returns the key with the given `kid`. When several keys share the
+    /// `kid`, this is the first one in the JWKS.
+    pub fn get(&self, kid: &str) -> Option<&Jwk> {
+        self.keys.get(kid)
     }
 
-    fn validate_url_scheme(url: &str) -> Result<(), JwksError> {
-        if !url.starts_with("http://") && !url.starts_with("https://") {
-            return Err(JwksError::InvalidUrlScheme(url.to_string()));
+    /// Returns the key with the given `kid` that can verify tokens signed
+    /// with `alg`, choosing among keys sharing the `kid` if necessary.
+    pub fn get_for(&self, kid: &str, alg: Algorithm) -> Option<&Jwk> {
+        self.keys_with_kid(kid).find(|jwk| jwk.supports(alg))
+    }
+
+    /// Returns every key that can verify tokens signed with `alg`, e.g. to
+    /// try each of them on a token without a `kid`.
+    pub fn candidates_for_alg(&self, alg: Algorithm) -> impl Iterator<Item = &Jwk> {
+        self.iter()
+            .map(|(_, jwk)| jwk)
+            .filter(move |jwk| jwk.supports(alg))
+    }
+
+    /// Returns every key published under `kid`. There is more than one only
+    /// when the set was built with [`DuplicateKids::KeepAll`].
+    pub fn keys_with_kid<'a, 'k>(
+        &'a self,
+        kid: &'k str,
+    ) -> impl Iterator<Item = &'a Jwk> + use<'a, 'k> {
+        self.keys.get(kid).into_iter().chain(
+            self.duplicates
+                .iter()
+                .filter(move |(duplicate_kid, _)| duplicate_kid == kid)
+                .map(|(_, jwk)| jwk),
+        )
+    }
+
+    /// Iterates over all keys and their `kid`s, including keys sharing a
+    /// `kid`.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Jwk)> {
+        self.keys
+            .iter()
+            .chain(self.duplicates.iter().map(|(kid, jwk)| (kid, jwk)))
+            .map(|(kid, jwk)| (kid.as_str(), jwk))
+    }
+
+    /// The number of keys in the set, including keys sharing a `kid`.
+    pub fn len(&self) -> usize {
+        self.keys.len() + self.duplicates.len()
+    }
+
+    /// Whether the set contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the set was loaded from a cache because the key set could not
+    /// be fetched, or is kept by a `RefreshingJwks` whose refresh failed.
+    pub fn is_stale(&self) -> bool {
+        self.stale_since.is_some()
+    }
+
+    /// For a [stale][Self::is_stale] set, when its keys were fetched, in
+    /// seconds since the epoch.
+    pub fn stale_since(&self) -> Option<u64> {
+        self.stale_since
+    }
+
+    /// The keys left out of the set because they violate the [`KeyPolicy`],
+    /// e.g. to log them or to export them as a metric.
+    pub fn rejected(&self) -> &[RejectedKey] {
+        &self.rejected
+    }
+
+    /// Finds the key with the given base64url `x5t` (SHA-1) or `x5t#S256`
+    /// (SHA-256) certificate thumbprint.
+    pub fn find_by_x5t(&self, thumbprint: &str) -> Option<&Jwk> {
+        self.iter().map(|(_, jwk)| jwk).find(|jwk| {
+            jwk.x5t.as_deref() == Some(thumbprint) || jwk.x5t_s256.as_deref() == Some(thumbprint)
+        })
+    }
+
+    /// Finds the key a token was signed with, by the `kid` in its header or,
+    /// for tokens that carry no `kid`, by its `x5t#S256` or `x5t` thumbprint.
+    ///
+    /// When several keys share the `kid`, the first one that
+    /// [supports][Jwk::supports] the header's `alg` is returned.
+    pub fn key_for_header(&self, header: &jsonwebtoken::Header) -> Option<&Jwk> {
+        if let Some(kid) = &header.kid {
+            return self.get_for(kid, header.alg).or_else(|| self.get(kid));
+        }
+        header
+            .x5t_s256
+            .as_deref()
+            .or(header.x5t.as_deref())
+            .and_then(|thumbprint| self.find_by_x5t(thumbprint))
+    }
+}
+
+/// Adds keys as [`DuplicateKids::KeepAll`] does: a key whose `kid` is
+/// already taken is kept alongside the existing one.
+#[allow(deprecated)]
+impl Extend<JwkEntry> for Jwks {
+    fn extend<I: IntoIterator<Item = JwkEntry>>(&mut self, entries: I) {
+        for JwkEntry { kid, jwk } in entries {
+            match self.keys.entry(kid) {
+                Entry::Vacant(entry) => {
+                    entry.insert(jwk);
+                }
+                Entry::Occupied(entry) => self.duplicates.push((entry.key().clone(), jwk)),
+            }
         }
-        Ok(())
+    }
+}
+
+#[allow(deprecated)]
+impl FromIterator<JwkEntry> for Jwks {
+    fn from_iter<I: IntoIterator<Item = JwkEntry>>(entries: I) -> Self {
+        let mut jwks = Self {
+            keys: HashMap::new(),
+            duplicates: Vec::new(),
+            stale_since: None,
+            rejected: Vec::new(),
+        };
+        jwks.extend(entries);
+        jwks
+    }
+}
+
+impl TryFrom<jwk::JwkSet> for Jwks {
+    type Error = JwksError;
+    fn try_from(jwks: jwk::JwkSet) -> Result<Self, Self::Error> {
+        Jwks::from_jwk_set(jwks)
     }
 }
 
@@ -92,6 +294,17 @@ pub struct Jwk {
     /// determine the algorithm from another source (e.g., the JWT header's `alg` field).
     pub alg: Option<KeyAlgorithm>,
     pub decoding_key: DecodingKey,
+    /// The key parameters the decoding key was built from, kept so the key
+    /// can be inspected or exported.
+    pub params: jwk::AlgorithmParameters,
+    /// The curve of an `EC` or `OKP` key.
+    pub curve: Option<jwk::EllipticCurve>,
+    /// The base64url SHA-1 thumbprint of the key's certificate (`x5t`).
+    /// With the `x509` feature it is computed from the `x5c` leaf certificate
+    /// when the key does not publish one.
+    pub x5t: Option<String>,
+    /// The base64url SHA-256 thumbprint of the key's certificate (`x5t#S256`).
+    pub x5t_s256: Option<String>,
 }
 
 #[allow(dead_code)]
@@ -102,8 +315,62 @@ pub struct JwkEntry {
 
 impl JwkEntry {
     pub fn from_jsonwebkey_ref(jwk: &jwk::Jwk) -> Result<Self, JwkError> {
+        Self::from_jsonwebkey_with_policy(jwk, &KeyPolicy::default())
+    }
+
+    /// A version of [`from_jsonwebkey_ref`][Self::from_jsonwebkey_ref] that
+    /// checks the key against a [`KeyPolicy`].
+    pub fn from_jsonwebkey_with_policy(
+        jwk: &jwk::Jwk,
+        policy: &KeyPolicy,
+    ) -> Result<Self, JwkError> {
         let kid = jwk.common.key_id.clone().ok_or(JwkError::MissingKeyId)?;
 
+        #[cfg(feature = "x509")]
+        let with_x5c_params = x509::with_params_from_x5c(jwk, policy.x5c.as_ref());
+        #[cfg(feature = "x509")]
+        let jwk = with_x5c_params.as_ref().unwrap_or(jwk);
+
+        let curve = match &jwk.algorithm {
+            jwk::AlgorithmParameters::EllipticCurve(params) => Some(params.curve.clone()),
+            jwk::AlgorithmParameters::OctetKeyPair(params) => Some(params.curve.clone()),
+            _ => None,
+        };
+        if let Some(curve) = &curve {
+            if !is_supported_curve(KeyType::of(&jwk.algorithm), curve_name(curve)) {
+                return Err(JwkError::UnsupportedCurve {
+                    key_id: kid,
+                    curve: curve_name(curve).to_string(),
+                });
+            }
+        }
+
+        policy.check(&kid, jwk)?;
+
+        // published thumbprints that do not match the leaf certificate fail
+        // the key; when no policy validates a chain whose leaf cannot be
+        // read, the key is kept without thumbprints, as they cannot be checked
+        #[cfg(feature = "x509")]
+        let (x5t, x5t_s256) = match (x509::check_thumbprints(jwk), &policy.x5c) {
+            (Ok(Some(thumbprints)), _) => (Some(thumbprints.sha1), Some(thumbprints.sha256)),
+            (Ok(None), _) => (
+                jwk.common.x509_sha1_fingerprint.clone(),
+                jwk.common.x509_sha256_fingerprint.clone(),
+            ),
+            (Err(X5cError::InvalidEncoding(_)), None) => (None, None),
+            (Err(error), _) => {
+                return Err(JwkError::X5cError {
+                    key_id: kid.clone(),
+                    error,
+                })
+            }
+        };
+        #[cfg(not(feature = "x509"))]
+        let (x5t, x5t_s256) = (
+            jwk.common.x509_sha1_fingerprint.clone(),
+            jwk.common.x509_sha256_fingerprint.clone(),
+        );
+
         let alg = jwk.common.key_algorithm;
 
         let decoding_key = match &jwk.algorithm {
@@ -135,11 +402,38 @@ impl JwkEntry {
 
         Ok(Self {
             kid,
-            jwk: Jwk { alg, decoding_key },
+            jwk: Jwk {
+                alg,
+                decoding_key,
+                params: jwk.algorithm.clone(),
+                curve,
+                x5t,
+                x5t_s256,
+            },
         })
     }
 }
 
+fn curve_name(curve: &jwk::EllipticCurve) -> &'static str {
+    match curve {
+        jwk::EllipticCurve::P256 => "P-256",
+        jwk::EllipticCurve::P384 => "P-384",
+        jwk::EllipticCurve::P521 => "P-521",
+        jwk::EllipticCurve::Ed25519 => "Ed25519",
+    }
+}
+
+/// Whether `crv` is a signature curve this crate handles for `kty`. Key
+/// agreement curves such as X25519, curves like secp256k1, and P-521, which
+/// `jsonwebtoken` has no algorithm for, are not.
+pub(crate) fn is_supported_curve(kty: KeyType, crv: &str) -> bool {
+    match kty {
+        KeyType::Ec => matches!(crv, "P-256" | "P-384"),
+        KeyType::Okp => crv == "Ed25519",
+        KeyType::Rsa | KeyType::Oct => false,
+    }
+}
+
 impl TryFrom<jwk::Jwk> for JwkEntry {
     type Error = JwkError;
     fn try_from(j: jwk::Jwk) -> Result<Self, Self::Error> {
@@ -153,20 +447,42 @@ pub enum JwksError {
     /// There was an error fetching the OIDC or JWKS config from
     /// the specified url.
     #[error("could not fetch config from authority: {0}")]
-    FetchError(#[from] reqwest::Error),
+    FetchError(#[source] FetchError),
 
     /// An error with an individual key caused the processing of the JWKS to
     /// fail.
     #[error("there was an error with an individual key: {0}")]
     KeyError(#[from] JwkError),
 
+    /// Two keys share a `kid` and the [`KeyPolicy`] refuses duplicates.
+    #[error("more than one key has the key id {0:?}")]
+    DuplicateKeyId(String),
+
     /// The URL is missing a required scheme (http:// or https://).
     #[error("URL scheme is required - URL must start with http:// or https://. Got: {0}")]
     InvalidUrlScheme(String),
+
+    /// The URL could not be parsed or has no host.
+    #[error("invalid URL: {0}")]
+    InvalidUrl(String),
+
+    /// The URL uses plain `http://` but the `FetchPolicy` requires HTTPS.
+    #[error("HTTPS is required by the fetch policy. Got: {0}")]
+    InsecureUrlScheme(String),
+
+    /// The `jwks_uri` from the OpenID configuration points at a host the
+    /// `FetchPolicy` does not allow.
+    #[error("the jwks_uri host is not allowed by the fetch policy. Got: {0}")]
+    DisallowedHost(String),
+
+    /// The URL points at a private, loopback or link-local address and the
+    /// `FetchPolicy` refuses those.
+    #[error("refusing to fetch from a private or loopback address. Got: {0}")]
+    PrivateAddress(String),
 }
 
 /// An error with a specific key from a JWKS.
-#[derive(Debug, Error)]
+#[derive(Clone, Debug, Error)]
 pub enum JwkError {
     /// There was an error constructing the decoding key from the RSA components
     /// provided by the key.
@@ -183,9 +499,59 @@ pub enum JwkError {
     /// The key is missing the `kid` attribute.
     #[error("the key is missing the `kid` attribute")]
     MissingKeyId,
+
+    /// The `crv` of an `EC` or `OKP` key is not a supported signature curve,
+    /// e.g. the key agreement curve X25519, secp256k1 or P-521.
+    #[error("the key {key_id:?} uses the unsupported curve {curve:?}")]
+    UnsupportedCurve { key_id: String, curve: String },
+
+    /// The RSA modulus is shorter than the [`KeyPolicy`] allows.
+    #[error("the key {key_id:?} has a {bits}-bit modulus, at least {min_bits} bits are required")]
+    KeyTooSmall {
+        key_id: String,
+        bits: usize,
+        min_bits: usize,
+    },
+
+    /// The key type is not allowed by the [`KeyPolicy`].
+    #[error("the key {key_id:?} has a disallowed key type {key_type:?}")]
+    DisallowedKeyType { key_id: String, key_type: KeyType },
+
+    /// The curve of an `EC` or `OKP` key is not allowed by the [`KeyPolicy`].
+    #[error("the key {key_id:?} uses the disallowed curve {curve:?}")]
+    DisallowedCurve {
+        key_id: String,
+        curve: jwk::EllipticCurve,
+    },
+
+    /// A PEM or DER encoded key could not be read.
+    #[cfg(feature = "pem")]
+    #[error("could not read the key {key_id:?}: {error}")]
+    PemError { key_id: String, error: PemError },
+
+    /// The `x5c` certificate chain of the key failed validation.
+    #[cfg(feature = "x509")]
+    #[error("the certificate chain of {key_id:?} is invalid: {error}")]
+    X5cError { key_id: String, error: X5cError },
 }
 
-#[cfg(test)]
+impl JwkError {
+    /// Whether the error leaves the key out of the set rather than failing
+    /// it: a [`KeyPolicy`] violation or an unsupported curve.
+    pub(crate) fn is_skippable(&self) -> bool {
+        match self {
+            JwkError::UnsupportedCurve { .. }
+            | JwkError::KeyTooSmall { .. }
+            | JwkError::DisallowedKeyType { .. }
+            | JwkError::DisallowedCurve { .. } => true,
+            #[cfg(feature = "x509")]
+            JwkError::X5cError { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "reqwest", not(target_arch = "wasm32")))]
 mod tests {
     use serde_json::json;
 
@@ -228,17 +594,15 @@ mod tests {
 
         let jwks_url = server.url(jwks_path);
         let jwks = Jwks::from_jwks_url(&jwks_url).await.unwrap();
-        assert_eq!(jwks.keys.len(), 2);
+        assert_eq!(jwks.len(), 2);
         assert_eq!(
-            jwks.keys
-                .get("91413cf4fa0cb92a3c3f5a054509132c47660937")
+            jwks.get("91413cf4fa0cb92a3c3f5a054509132c47660937")
                 .unwrap()
                 .alg,
             Some(KeyAlgorithm::RS256)
         );
         assert_eq!(
-            jwks.keys
-                .get("1f40f0a8ef3d880978dc82f25c3ec317c6a5b781")
+            jwks.get("1f40f0a8ef3d880978dc82f25c3ec317c6a5b781")
                 .unwrap()
                 .alg,
             Some(KeyAlgorithm::RS256)
@@ -246,11 +610,9 @@ mod tests {
 
         // get keys by key id (kid)
         _ = &jwks
-            .keys
             .get("91413cf4fa0cb92a3c3f5a054509132c47660937")
             .expect("key one should be found");
         _ = &jwks
-            .keys
             .get("1f40f0a8ef3d880978dc82f25c3ec317c6a5b781")
             .expect("key two should be found");
     }
@@ -361,17 +723,15 @@ mod tests {
 
         let oidc_config_url = oidc_server.url(oidc_config_path);
         let jwks = Jwks::from_oidc_url(&oidc_config_url).await.unwrap();
-        assert_eq!(jwks.keys.len(), 2);
+        assert_eq!(jwks.len(), 2);
         assert_eq!(
-            jwks.keys
-                .get("91413cf4fa0cb92a3c3f5a054509132c47660937")
+            jwks.get("91413cf4fa0cb92a3c3f5a054509132c47660937")
                 .unwrap()
                 .alg,
             Some(KeyAlgorithm::RS256)
         );
         assert_eq!(
-            jwks.keys
-                .get("1f40f0a8ef3d880978dc82f25c3ec317c6a5b781")
+            jwks.get("1f40f0a8ef3d880978dc82f25c3ec317c6a5b781")
                 .unwrap()
                 .alg,
             Some(KeyAlgorithm::RS256)
@@ -379,11 +739,9 @@ mod tests {
 
         // get keys by key id (kid)
         _ = &jwks
-            .keys
             .get("91413cf4fa0cb92a3c3f5a054509132c47660937")
             .expect("key one should be found");
         _ = &jwks
-            .keys
             .get("1f40f0a8ef3d880978dc82f25c3ec317c6a5b781")
             .expect("key two should be found");
     }
@@ -513,8 +871,8 @@ mod tests {
         });
 
         let jwks = Jwks::from_jwks_url(&server.url("/no-alg")).await.unwrap();
-        assert_eq!(jwks.keys.len(), 1);
-        let key = jwks.keys.get("no-alg-key").unwrap();
+        assert_eq!(jwks.len(), 1);
+        let key = jwks.get("no-alg-key").unwrap();
         assert_eq!(key.alg, None);
     }
 
@@ -531,7 +889,7 @@ mod tests {
         });
 
         let jwks = Jwks::from_jwks_url(&server.url("/empty")).await.unwrap();
-        assert_eq!(jwks.keys.len(), 0);
+        assert_eq!(jwks.len(), 0);
     }
 
     #[tokio::test]
@@ -572,6 +930,258 @@ mod tests {
         assert!(matches!(result, Err(JwksError::InvalidUrlScheme(_))));
     }
 
+    #[tokio::test]
+    async fn rejects_discovered_jwks_uri_without_http_scheme() {
+        let server = MockServer::start();
+        let _ = server.mock(|when, then| {
+            when.method(GET).path("/.well-known/openid-configuration");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(json!({ "jwks_uri": "file:///etc/passwd" }).to_string());
+        });
+
+        let result = Jwks::from_oidc_url(&server.url("/.well-known/openid-configuration")).await;
+        assert!(matches!(result, Err(JwksError::InvalidUrlScheme(_))));
+    }
+
+    #[tokio::test]
+    async fn strict_policy_rejects_cross_origin_jwks_uri() {
+        let oidc_server = MockServer::start();
+        let jwks_server = MockServer::start();
+        let _ = oidc_server.mock(|when, then| {
+            when.method(GET).path("/.well-known/openid-configuration");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(json!({ "jwks_uri": jwks_server.url("/certs") }).to_string());
+        });
+        let jwks_mock = jwks_server.mock(|when, then| {
+            when.method(GET).path("/certs");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(json!({ "keys": [] }).to_string());
+        });
+
+        let policy = FetchPolicy {
+            allow_insecure_localhost: true,
+            ..FetchPolicy::strict()
+        };
+        let result = Jwks::from_oidc_url_with_policy(
+            &reqwest::Client::default(),
+            oidc_server.url("/.well-known/openid-configuration"),
+            &policy,
+        )
+        .await;
+        assert!(matches!(result, Err(JwksError::DisallowedHost(_))));
+        jwks_mock.assert_hits(0);
+    }
+
+    #[tokio::test]
+    async fn strict_policy_with_localhost_opt_out_fetches_same_origin_jwks_uri() {
+        let server = MockServer::start();
+        let _ = server.mock(|when, then| {
+            when.method(GET).path("/.well-known/openid-configuration");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(json!({ "jwks_uri": server.url("/certs") }).to_string());
+        });
+        let _ = server.mock(|when, then| {
+            when.method(GET).path("/certs");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(json!({ "keys": [] }).to_string());
+        });
+
+        let oidc_url = server.url("/.well-known/openid-configuration");
+        let result = Jwks::from_oidc_url_with_policy(
+            &reqwest::Client::default(),
+            &oidc_url,
+            &FetchPolicy::strict(),
+        )
+        .await;
+        assert!(matches!(result, Err(JwksError::InsecureUrlScheme(_))));
+
+        let policy = FetchPolicy {
+            allow_insecure_localhost: true,
+            ..FetchPolicy::strict()
+        };
+        let jwks = Jwks::from_oidc_url_with_policy(&reqwest::Client::default(), &oidc_url, &policy)
+            .await
+            .unwrap();
+        assert_eq!(jwks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn strict_policy_checks_redirects() {
+        let server = MockServer::start();
+        let _ = server.mock(|when, then| {
+            when.method(GET).path("/certs");
+            then.status(302)
+                .header("location", "https://169.254.169.254/latest/meta-data");
+        });
+        let policy = FetchPolicy {
+            allow_insecure_localhost: true,
+            ..FetchPolicy::strict()
+        };
+
+        let client = reqwest::Client::builder()
+            .redirect(policy.redirect_policy())
+            .build()
+            .unwrap();
+        let result = Jwks::from_jwks_url_with_policy(&client, server.url("/certs"), &policy).await;
+        let Err(JwksError::FetchError(error)) = result else {
+            panic!("the redirect should have been refused");
+        };
+        assert!(format!("{error:?}").contains("PrivateAddress"));
+    }
+
+    struct StubFetcher {
+        responses: HashMap<String, serde_json::Value>,
+    }
+
+    impl JwksFetcher for StubFetcher {
+        async fn fetch(&self, url: &str) -> Result<FetchResponse, FetchError> {
+            let body = self
+                .responses
+                .get(url)
+                .ok_or_else(|| format!("no stub response for {url}"))?;
+            Ok(FetchResponse {
+                body: body.to_string().into_bytes(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn can_fetch_through_custom_fetcher() {
+        let fetcher = StubFetcher {
+            responses: HashMap::from([
+                (
+                    "https://auth.example.com/.well-known/openid-configuration".to_string(),
+                    json!({ "jwks_uri": "https://auth.example.com/keys" }),
+                ),
+                (
+                    "https://auth.example.com/keys".to_string(),
+                    json!({
+                      "keys": [{
+                        "kty": "EC",
+                        "alg": "ES256",
+                        "crv": "P-256",
+                        "x": "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4",
+                        "y": "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM",
+                        "kid": "stub-key"
+                      }]
+                    }),
+                ),
+            ]),
+        };
+
+        let jwks = Jwks::from_oidc_url_with_client(
+            &fetcher,
+            "https://auth.example.com/.well-known/openid-configuration",
+        )
+        .await
+        .unwrap();
+        assert_eq!(jwks.get("stub-key").unwrap().alg, Some(KeyAlgorithm::ES256));
+
+        let result =
+            Jwks::from_jwks_url_with_client(&fetcher, "https://other.example.com/keys").await;
+        assert!(matches!(result, Err(JwksError::FetchError(_))));
+    }
+
+    #[test]
+    fn reqwest_fetch_futures_are_send() {
+        fn assert_send<T: Send>(_: T) {}
+        let client = reqwest::Client::default();
+        assert_send(Jwks::from_jwks_url("https://example.com/jwks"));
+        assert_send(Jwks::from_oidc_url_with_client(
+            &client,
+            "https://example.com/.well-known/openid-configuration",
+        ));
+    }
+
+    #[tokio::test]
+    async fn reqwest_fetcher_returns_body_and_headers() {
+        let server = MockServer::start();
+        let _ = server.mock(|when, then| {
+            when.method(GET).path("/jwks");
+            then.status(200)
+                .header("Cache-Control", "max-age=3600")
+                .body("{}");
+        });
+
+        let response = reqwest::Client::default()
+            .fetch(&server.url("/jwks"))
+            .await
+            .unwrap();
+        assert_eq!(response.body, b"{}");
+        assert_eq!(response.header("cache-control"), Some("max-age=3600"));
+        assert_eq!(response.header("Cache-Control"), Some("max-age=3600"));
+    }
+
+    #[test]
+    fn can_build_jwks_from_jwk_set() {
+        let jwk_set: jwk::JwkSet = serde_json::from_value(json!({
+          "keys": [
+            {
+              "kty": "oct",
+              "alg": "HS256",
+              "k": "GawgguFyGrWKav7AX4VKUg",
+              "kid": "local-key"
+            }
+          ]
+        }))
+        .unwrap();
+
+        let jwks = Jwks::try_from(jwk_set).unwrap();
+        assert_eq!(
+            jwks.get("local-key").unwrap().alg,
+            Some(KeyAlgorithm::HS256)
+        );
+    }
+
+    #[test]
+    fn can_query_keys_by_kid_and_alg() {
+        let jwk_set: jwk::JwkSet = serde_json::from_value(json!({
+          "keys": [
+            {
+              "kty": "EC",
+              "crv": "P-256",
+              "x": "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4",
+              "y": "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM",
+              "kid": "shared"
+            },
+            {
+              "kty": "oct",
+              "alg": "HS256",
+              "k": "GawgguFyGrWKav7AX4VKUg",
+              "kid": "shared"
+            },
+            {
+              "kty": "oct",
+              "alg": "HS384",
+              "k": "GawgguFyGrWKav7AX4VKUg",
+              "kid": "other"
+            }
+          ]
+        }))
+        .unwrap();
+        let jwks = Jwks::from_jwk_set(jwk_set).unwrap();
+
+        assert_eq!(jwks.len(), 3);
+        assert!(!jwks.is_empty());
+        assert_eq!(jwks.iter().filter(|(kid, _)| *kid == "shared").count(), 2);
+        assert!(jwks.get("shared").unwrap().curve.is_some());
+        assert!(jwks.get("missing").is_none());
+
+        let hmac = jwks.get_for("shared", Algorithm::HS256).unwrap();
+        assert_eq!(hmac.alg, Some(KeyAlgorithm::HS256));
+        assert!(jwks.get_for("shared", Algorithm::HS384).is_none());
+        assert!(jwks.get_for("shared", Algorithm::ES256).is_some());
+
+        assert_eq!(jwks.candidates_for_alg(Algorithm::HS384).count(), 1);
+        assert_eq!(jwks.candidates_for_alg(Algorithm::RS256).count(), 0);
+    }
+
     #[tokio::test]
     async fn can_parse_ec_keys_with_ec_p256_algorithms() {
         let server = MockServer::start();
@@ -599,10 +1209,10 @@ mod tests {
         });
 
         let jwks = Jwks::from_jwks_url(&server.url(jwks_path)).await.unwrap();
-        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwks.len(), 1);
 
         // Test P-256 key
-        let p256_key = jwks.keys.get("ec-p256-key").unwrap();
+        let p256_key = jwks.get("ec-p256-key").unwrap();
         assert_eq!(p256_key.alg, Some(KeyAlgorithm::ES256));
     }
 
@@ -699,6 +1309,99 @@ mod tests {
         assert!(matches!(result, Err(JwksError::FetchError(_))));
     }
 
+    #[tokio::test]
+    async fn skips_keys_on_unsupported_curves() {
+        let fetcher = StubFetcher {
+            responses: HashMap::from([(
+                "https://auth.example.com/keys".to_string(),
+                json!({
+                  "keys": [
+                    {
+                      "kty": "OKP",
+                      "crv": "X25519",
+                      "use": "enc",
+                      "x": "hSDwCYkwp1R0i33ctD73Wg2_Og0mOBr066SpjqqbTmo",
+                      "kid": "agreement-key"
+                    },
+                    {
+                      "kty": "EC",
+                      "crv": "secp256k1",
+                      "x": "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4",
+                      "y": "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM"
+                    },
+                    {
+                      "kty": "EC",
+                      "crv": "P-256",
+                      "x": "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4",
+                      "y": "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM",
+                      "kid": "ec-key"
+                    }
+                  ]
+                }),
+            )]),
+        };
+        let jwks = Jwks::from_jwks_url_with_client(&fetcher, "https://auth.example.com/keys")
+            .await
+            .unwrap();
+        assert!(jwks.get("ec-key").is_some());
+        let rejected = jwks.rejected();
+        assert_eq!(rejected.len(), 2);
+        assert_eq!(rejected[0].index, 0);
+        assert_eq!(rejected[0].kid.as_deref(), Some("agreement-key"));
+        assert!(matches!(
+            &rejected[0].error,
+            JwkError::UnsupportedCurve { key_id, curve }
+                if key_id == "agreement-key" && curve == "X25519"
+        ));
+        // a key without a kid is named by its position
+        assert_eq!(rejected[1].index, 1);
+        assert_eq!(rejected[1].kid, None);
+        assert!(matches!(
+            &rejected[1].error,
+            JwkError::UnsupportedCurve { key_id, curve }
+                if key_id == "keys[1]" && curve == "secp256k1"
+        ));
+
+        let mut policy = FetchPolicy::default();
+        policy.keys.fail_on_violation = true;
+        let result =
+            Jwks::from_jwks_url_with_policy(&fetcher, "https://auth.example.com/keys", &policy)
+                .await;
+        assert!(matches!(
+            result,
+            Err(JwksError::KeyError(JwkError::UnsupportedCurve { key_id, .. }))
+                if key_id == "agreement-key"
+        ));
+
+        // an OKP key claiming a NIST curve is not treated as Ed25519, and
+        // P-521 keys, which no algorithm can verify with, are left out too
+        let jwk_set: jwk::JwkSet = serde_json::from_value(json!({
+          "keys": [
+            {
+              "kty": "OKP",
+              "crv": "P-256",
+              "x": "hSDwCYkwp1R0i33ctD73Wg2_Og0mOBr066SpjqqbTmo",
+              "kid": "confused-key"
+            },
+            {
+              "kty": "EC",
+              "crv": "P-521",
+              "x": "AHKZLLOsCOzz5cY97ewNUajB957y-C-U88c3v13nmGZx6sYl_oJXu9A5RkTKqjqvjyekWF-7ytDyRXYgCF5cj0Kt",
+              "y": "AdymlHvOiLxXkEhayXF4GiQ2Y3eFx8Ms26cDEvFx-Fp-hkvE3Rr_EdXA8ouUrHd1lSz4lBzPfzg6g-0K1Oli0zmT",
+              "kid": "p521-key"
+            }
+          ]
+        }))
+        .unwrap();
+        let jwks = Jwks::from_jwk_set(jwk_set).unwrap();
+        assert!(jwks.is_empty());
+        assert!(jwks
+            .rejected()
+            .iter()
+            .all(|rejected| matches!(rejected.error, JwkError::UnsupportedCurve { .. })));
+        assert_eq!(jwks.rejected().len(), 2);
+    }
+
     #[tokio::test]
     async fn can_parse_mixed_key_types() {
         let server = MockServer::start();
@@ -741,19 +1444,48 @@ mod tests {
         });
 
         let jwks = Jwks::from_jwks_url(&server.url(jwks_path)).await.unwrap();
-        assert_eq!(jwks.keys.len(), 3);
+        assert_eq!(jwks.len(), 2);
 
         // Test RSA key
-        let rsa_key = jwks.keys.get("rsa-key").unwrap();
+        let rsa_key = jwks.get("rsa-key").unwrap();
         assert_eq!(rsa_key.alg, Some(KeyAlgorithm::RS256));
 
         // Test EC key
-        let ec_key = jwks.keys.get("ec-key").unwrap();
+        let ec_key = jwks.get("ec-key").unwrap();
         assert_eq!(ec_key.alg, Some(KeyAlgorithm::ES256));
 
-        // Test symmetric key
-        let symmetric_key = jwks.keys.get("symmetric-key").unwrap();
-        assert_eq!(symmetric_key.alg, Some(KeyAlgorithm::HS256));
+        // The symmetric key is left out and reported, so a tampered response
+        // cannot be used to mint HMAC-signed tokens
+        assert!(jwks.get("symmetric-key").is_none());
+        let rejected = jwks.rejected();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].index, 2);
+        assert_eq!(rejected[0].kid.as_deref(), Some("symmetric-key"));
+        assert!(matches!(
+            rejected[0].error,
+            JwkError::DisallowedKeyType {
+                key_type: KeyType::Oct,
+                ..
+            }
+        ));
+
+        let mut header = jsonwebtoken::Header::new(Algorithm::HS256);
+        header.kid = Some("symmetric-key".to_string());
+        let forged = jsonwebtoken::encode(
+            &header,
+            &json!({ "sub": "mallory", "exp": jsonwebtoken::get_current_timestamp() + 60 }),
+            &jsonwebtoken::EncodingKey::from_secret(
+                &URL_SAFE_NO_PAD.decode("GawgguFyGrWKav7AX4VKUg").unwrap(),
+            ),
+        )
+        .unwrap();
+        assert!(matches!(
+            jwks.decode::<serde_json::Value>(
+                &forged,
+                &jsonwebtoken::Validation::new(Algorithm::HS256)
+            ),
+            Err(VerifyError::UnknownKey)
+        ));
     }
 
     #[tokio::test]
@@ -816,7 +1548,7 @@ mod tests {
         let token = encode(&header, &claims, &encoding_key).unwrap();
 
         // Verify JWT using the fetched JWKS
-        let test_key = jwks.keys.get("test-rsa-key").unwrap();
+        let test_key = jwks.get("test-rsa-key").unwrap();
         let validation = Validation::new(jsonwebtoken::Algorithm::RS256);
         let token_data =
             decode::<serde_json::Value>(&token, &test_key.decoding_key, &validation).unwrap();
@@ -863,7 +1595,11 @@ mod tests {
 
         // Fetch JWKS using the library
         let jwks_url = server.url(jwks_path);
-        let jwks = Jwks::from_jwks_url(&jwks_url).await.unwrap();
+        let mut policy = FetchPolicy::default();
+        policy.keys.allow_symmetric = true;
+        let jwks = Jwks::from_jwks_url_with_policy(&reqwest::Client::default(), &jwks_url, &policy)
+            .await
+            .unwrap();
 
         // Create JWT claims (with future expiration)
         let now = jsonwebtoken::get_current_timestamp();
@@ -880,7 +1616,7 @@ mod tests {
         let token = encode(&header, &claims, &encoding_key).unwrap();
 
         // Verify JWT using the fetched JWKS
-        let test_key = jwks.keys.get("test-symmetric-key").unwrap();
+        let test_key = jwks.get("test-symmetric-key").unwrap();
         let validation = Validation::new(jsonwebtoken::Algorithm::HS256);
         let token_data =
             decode::<serde_json::Value>(&token, &test_key.decoding_key, &validation).unwrap();
@@ -972,7 +1708,7 @@ mod tests {
         let token = encode(&header, &claims, &encoding_key).unwrap();
 
         // Verify JWT using the fetched JWKS
-        let test_key = jwks.keys.get("test-oidc-key").unwrap();
+        let test_key = jwks.get("test-oidc-key").unwrap();
         let mut validation = Validation::new(jsonwebtoken::Algorithm::RS256);
         validation.set_issuer(&["https://auth.example.com"]);
         let token_data =
@@ -0,0 +1,385 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use url::{Host, Url};
+
+use crate::{FetchResponse, JwksError, KeyPolicy};
+
+/// Rules applied to every URL the crate fetches from, including the
+/// `jwks_uri` found in an OpenID configuration document.
+///
+/// [`FetchPolicy::default`] keeps the historical, permissive behaviour: both
+/// `http://` and `https://` are accepted and the discovered `jwks_uri` may
+/// point anywhere. Use [`FetchPolicy::strict`] in production.
+///
+/// The url a response was finally served from, after redirects, is checked
+/// too, but only once every redirect has been followed: a client that
+/// follows redirects, such as a default `reqwest::Client`, still sends the
+/// requests the policy refuses, e.g. to a private address. A client passed
+/// along with a policy must therefore be built with the policy's
+/// `redirect_policy`, which checks each redirect before following it, or
+/// not follow redirects at all.
+#[derive(Clone, Debug)]
+pub struct FetchPolicy {
+    /// Only accept `https://` URLs.
+    pub https_only: bool,
+    /// Exempt `localhost` and loopback addresses from `https_only` and
+    /// `deny_private_ips`, e.g. to test against a local mock server.
+    ///
+    /// This also lets a discovered `jwks_uri` or a redirect point at a
+    /// loopback service, so do not enable it in production.
+    pub allow_insecure_localhost: bool,
+    /// Which hosts the `jwks_uri` of an OpenID configuration may point at.
+    pub jwks_uri_hosts: JwksUriHosts,
+    /// Refuse URLs whose host is a private, loopback, link-local or otherwise
+    /// non-public IP address.
+    ///
+    /// Only IP literals and `localhost` names are checked; host names are not
+    /// resolved. To also guard against DNS names resolving to internal
+    /// addresses, configure a resolver on the HTTP client.
+    pub deny_private_ips: bool,
+    /// Rules applied to every key in the fetched JWKS. Defaults to
+    /// [`KeyPolicy::remote`], which leaves out symmetric `oct` keys and lists
+    /// them in [`Jwks::rejected`][crate::Jwks::rejected]; set
+    /// `keys.allow_symmetric` to accept them.
+    pub keys: KeyPolicy,
+}
+
+/// The hosts a discovered `jwks_uri` is allowed to point at.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum JwksUriHosts {
+    /// Any host is accepted.
+    #[default]
+    Any,
+    /// The `jwks_uri` must have the same scheme, host and port as the
+    /// OpenID configuration url it was discovered from.
+    SameOrigin,
+    /// The `jwks_uri` host must be the same origin as the OpenID configuration
+    /// url, or one of the listed host names (e.g. `www.googleapis.com`).
+    Allowlist(Vec<String>),
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        Self {
+            https_only: false,
+            allow_insecure_localhost: false,
+            jwks_uri_hosts: JwksUriHosts::Any,
+            deny_private_ips: false,
+            keys: KeyPolicy::remote(),
+        }
+    }
+}
+
+impl FetchPolicy {
+    /// A policy that requires HTTPS, refuses private and loopback addresses
+    /// and only follows a `jwks_uri` on the same origin as the OpenID
+    /// configuration.
+    pub fn strict() -> Self {
+        Self {
+            https_only: true,
+            allow_insecure_localhost: false,
+            jwks_uri_hosts: JwksUriHosts::SameOrigin,
+            deny_private_ips: true,
+            keys: KeyPolicy::remote(),
+        }
+    }
+
+    /// Checks a url the caller asked us to fetch from.
+    pub(crate) fn check_url(&self, url: &str) -> Result<Url, JwksError> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(JwksError::InvalidUrlScheme(url.to_string()));
+        }
+        let parsed = Url::parse(url).map_err(|_| JwksError::InvalidUrl(url.to_string()))?;
+        let host = parsed
+            .host()
+            .ok_or_else(|| JwksError::InvalidUrl(url.to_string()))?;
+
+        let localhost_exempt = self.allow_insecure_localhost && is_loopback_host(&host);
+
+        if self.https_only && parsed.scheme() != "https" && !localhost_exempt {
+            return Err(JwksError::InsecureUrlScheme(url.to_string()));
+        }
+        if self.deny_private_ips && is_private_host(&host) && !localhost_exempt {
+            return Err(JwksError::PrivateAddress(url.to_string()));
+        }
+
+        Ok(parsed)
+    }
+
+    /// Checks the url a response was served from, when the fetcher followed
+    /// redirects.
+    pub(crate) fn check_response(&self, response: &FetchResponse) -> Result<(), JwksError> {
+        match &response.url {
+            Some(url) => self.check_url(url).map(drop),
+            None => Ok(()),
+        }
+    }
+
+    /// A `reqwest` redirect policy that checks every redirect against this
+    /// policy before following it, and follows at most 10 of them.
+    ///
+    /// ```no_run
+    /// # use jwks::FetchPolicy;
+    /// let policy = FetchPolicy::strict();
+    /// let client = reqwest::Client::builder()
+    ///     .redirect(policy.redirect_policy())
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[cfg(all(feature = "reqwest", not(target_arch = "wasm32")))]
+    pub fn redirect_policy(&self) -> reqwest::redirect::Policy {
+        let policy = self.clone();
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= 10 {
+                return attempt.error("too many redirects");
+            }
+            match policy.check_url(attempt.url().as_str()) {
+                Ok(_) => attempt.follow(),
+                Err(error) => attempt.error(error),
+            }
+        })
+    }
+
+    /// Checks a `jwks_uri` discovered in the OpenID configuration fetched
+    /// from `oidc_url`.
+    pub(crate) fn check_jwks_uri(&self, oidc_url: &Url, jwks_uri: &str) -> Result<Url, JwksError> {
+        let parsed = self.check_url(jwks_uri)?;
+
+        let allowed = match &self.jwks_uri_hosts {
+            JwksUriHosts::Any => true,
+            JwksUriHosts::SameOrigin => parsed.origin() == oidc_url.origin(),
+            JwksUriHosts::Allowlist(hosts) => {
+                parsed.origin() == oidc_url.origin()
+                    || parsed
+                        .host_str()
+                        .is_some_and(|host| hosts.iter().any(|h| h.eq_ignore_ascii_case(host)))
+            }
+        };
+        if !allowed {
+            return Err(JwksError::DisallowedHost(jwks_uri.to_string()));
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn is_loopback_host(host: &Host<&str>) -> bool {
+    match host {
+        Host::Domain(domain) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+        Host::Ipv4(ip) => ip.is_loopback(),
+        Host::Ipv6(ip) => {
+            ip.is_loopback() || ip.to_ipv4_mapped().is_some_and(|v4| v4.is_loopback())
+        }
+    }
+}
+
+fn is_private_host(host: &Host<&str>) -> bool {
+    match host {
+        Host::Domain(_) => is_loopback_host(host),
+        Host::Ipv4(ip) => is_private_ip(IpAddr::V4(*ip)),
+        Host::Ipv6(ip) => is_private_ip(IpAddr::V6(*ip)),
+    }
+}
+
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_ipv4(ip),
+        IpAddr::V6(ip) => is_private_ipv6(ip),
+    }
+}
+
+fn is_private_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this network" (RFC 791)
+        || a == 0
+        // shared address space (RFC 6598)
+        || (a == 100 && (b & 0b1100_0000) == 64)
+        // benchmarking (RFC 2544)
+        || (a == 198 && (b & 0b1111_1110) == 18)
+        // reserved (RFC 1112), including the broadcast address
+        || a >= 240
+}
+
+fn is_private_ipv6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // IPv4-mapped addresses and the well-known NAT64 prefix (RFC 6052)
+    // reach the embedded IPv4 address
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_private_ipv4(v4);
+    }
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., a, b, c, d] = ip.octets();
+        return is_private_ipv4(Ipv4Addr::new(a, b, c, d));
+    }
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        || ip.is_multicast()
+        // local-use NAT64 (RFC 8215)
+        || segments[..3] == [0x64, 0xff9b, 1]
+        // documentation (RFC 3849)
+        || segments[..2] == [0x2001, 0xdb8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_policy_accepts_http_and_https() {
+        let policy = FetchPolicy::default();
+        assert!(policy.check_url("http://example.com/jwks").is_ok());
+        assert!(policy.check_url("https://example.com/jwks").is_ok());
+        assert!(policy.check_url("http://127.0.0.1:8080/jwks").is_ok());
+    }
+
+    #[test]
+    fn rejects_url_without_scheme() {
+        let result = FetchPolicy::default().check_url("example.com/jwks");
+        assert!(matches!(result, Err(JwksError::InvalidUrlScheme(_))));
+    }
+
+    #[test]
+    fn strict_policy_requires_https() {
+        let result = FetchPolicy::strict().check_url("http://example.com/jwks");
+        assert!(matches!(result, Err(JwksError::InsecureUrlScheme(_))));
+        assert!(FetchPolicy::strict()
+            .check_url("https://example.com/jwks")
+            .is_ok());
+    }
+
+    #[test]
+    fn strict_policy_refuses_private_addresses() {
+        let policy = FetchPolicy::strict();
+        for url in [
+            "https://127.0.0.1/jwks",
+            "https://10.0.0.1/jwks",
+            "https://192.168.1.1/jwks",
+            "https://169.254.169.254/latest/meta-data",
+            "https://100.64.0.1/jwks",
+            "https://[::1]/jwks",
+            "https://[fd00::1]/jwks",
+            "https://[::ffff:10.0.0.1]/jwks",
+            "https://localhost/jwks",
+            "https://0.1.2.3/jwks",
+            "https://198.18.0.1/jwks",
+            "https://198.19.255.254/jwks",
+            "https://224.0.0.1/jwks",
+            "https://239.255.255.250/jwks",
+            "https://255.255.255.255/jwks",
+            "https://[::ffff:169.254.169.254]/jwks",
+            "https://[64:ff9b::a9fe:a9fe]/jwks",
+            "https://[64:ff9b::7f00:1]/jwks",
+            "https://[64:ff9b:1::1]/jwks",
+            "https://[ff02::1]/jwks",
+            "https://[ff05::2]/jwks",
+        ] {
+            assert!(
+                matches!(policy.check_url(url), Err(JwksError::PrivateAddress(_))),
+                "{url} should be refused"
+            );
+        }
+    }
+
+    #[test]
+    fn strict_policy_accepts_public_addresses() {
+        let policy = FetchPolicy::strict();
+        for url in [
+            "https://8.8.8.8/jwks",
+            "https://198.20.0.1/jwks",
+            "https://[2606:4700::1111]/jwks",
+            "https://[::ffff:8.8.8.8]/jwks",
+            "https://[64:ff9b::808:808]/jwks",
+        ] {
+            assert!(policy.check_url(url).is_ok(), "{url} should be accepted");
+        }
+    }
+
+    #[test]
+    fn localhost_opt_out_allows_insecure_loopback_only() {
+        let policy = FetchPolicy {
+            allow_insecure_localhost: true,
+            ..FetchPolicy::strict()
+        };
+        assert!(policy.check_url("http://localhost:8080/jwks").is_ok());
+        assert!(policy.check_url("http://127.0.0.1:8080/jwks").is_ok());
+        assert!(matches!(
+            policy.check_url("http://example.com/jwks"),
+            Err(JwksError::InsecureUrlScheme(_))
+        ));
+        assert!(matches!(
+            policy.check_url("https://10.0.0.1/jwks"),
+            Err(JwksError::PrivateAddress(_))
+        ));
+    }
+
+    #[test]
+    fn same_origin_jwks_uri() {
+        let policy = FetchPolicy::strict();
+        let oidc_url =
+            Url::parse("https://auth.example.com/.well-known/openid-configuration").unwrap();
+        assert!(policy
+            .check_jwks_uri(&oidc_url, "https://auth.example.com/keys")
+            .is_ok());
+        assert!(matches!(
+            policy.check_jwks_uri(&oidc_url, "https://evil.example.net/keys"),
+            Err(JwksError::DisallowedHost(_))
+        ));
+        assert!(matches!(
+            policy.check_jwks_uri(&oidc_url, "http://auth.example.com/keys"),
+            Err(JwksError::InsecureUrlScheme(_))
+        ));
+    }
+
+    #[test]
+    fn allowlisted_jwks_uri() {
+        let policy = FetchPolicy {
+            jwks_uri_hosts: JwksUriHosts::Allowlist(vec!["www.googleapis.com".to_string()]),
+            ..FetchPolicy::strict()
+        };
+        let oidc_url =
+            Url::parse("https://accounts.google.com/.well-known/openid-configuration").unwrap();
+        assert!(policy
+            .check_jwks_uri(&oidc_url, "https://www.googleapis.com/oauth2/v3/certs")
+            .is_ok());
+        assert!(matches!(
+            policy.check_jwks_uri(&oidc_url, "https://attacker.example.com/certs"),
+            Err(JwksError::DisallowedHost(_))
+        ));
+    }
+
+    #[test]
+    fn checks_the_url_a_response_was_served_from() {
+        let redirected = FetchResponse {
+            url: Some("https://169.254.169.254/latest/meta-data".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            FetchPolicy::strict().check_response(&redirected),
+            Err(JwksError::PrivateAddress(_))
+        ));
+        FetchPolicy::default().check_response(&redirected).unwrap();
+        FetchPolicy::strict()
+            .check_response(&FetchResponse::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn default_policy_still_checks_jwks_uri_scheme() {
+        let oidc_url = Url::parse("http://localhost/.well-known/openid-configuration").unwrap();
+        assert!(matches!(
+            FetchPolicy::default().check_jwks_uri(&oidc_url, "file:///etc/passwd"),
+            Err(JwksError::InvalidUrlScheme(_))
+        ));
+    }
+}
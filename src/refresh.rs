@@ -0,0 +1,720 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, RwLock,
+    },
+    time::Duration,
+};
+
+use jsonwebtoken::get_current_timestamp;
+use tokio::sync::broadcast;
+
+use crate::{
+    http::discover_jwks_uri, store::load, FetchPolicy, Jwk, JwkEntry, Jwks, JwksError, JwksFetcher,
+    JwksStore, KeyRef, KeySetDiff, MergeConflict,
+};
+
+/// How many key set changes a slow subscriber may fall behind before missing
+/// some.
+const EVENT_CAPACITY: usize = 16;
+
+/// A [`Jwks`] that can be reloaded from its JWKS url, notifying subscribers
+/// of key rotations.
+///
+/// Reloading happens when [`refresh`][Self::refresh] is called, e.g. from a
+/// timer task, so no particular async runtime is required.
+///
+/// By default a key removed from the upstream JWKS is dropped at the next
+/// refresh. With a [grace period][Self::with_grace_period] it stays usable,
+/// and is listed as [retiring][Self::retiring], until the grace period has
+/// passed.
+///
+/// When a refresh fails, the current keys stay in service, marked
+/// [stale][Jwks::is_stale], and the failure is reported by
+/// [`health`][Self::health]. With a [maximum staleness][Self::with_max_staleness]
+/// they are only served until it has passed.
+///
+/// With a [store][Self::from_jwks_url_with_store], key sets are looked up in
+/// a [`JwksStore`] shared with other refreshers before fetching from the
+/// identity provider.
+pub struct RefreshingJwks<F, S = ()> {
+    fetcher: F,
+    jwks_url: String,
+    policy: FetchPolicy,
+    store: S,
+    /// How long fetched key sets are kept in the store.
+    store_ttl: Duration,
+    grace_period: Duration,
+    max_staleness: Option<Duration>,
+    /// The keys served: the upstream keys and the retiring ones.
+    keys: RwLock<Arc<Jwks>>,
+    /// When the first retiring key is due for eviction, so that reading the
+    /// keys only takes the state lock when there is something to evict.
+    next_eviction: AtomicU64,
+    state: Mutex<State>,
+    events: broadcast::Sender<KeySetDiff>,
+}
+
+struct State {
+    /// The key set last fetched.
+    upstream: Jwks,
+    retiring: Vec<Retiring>,
+    health: RefreshHealth,
+}
+
+struct Retiring {
+    kid: String,
+    jwk: Jwk,
+    removed_at: u64,
+}
+
+/// A key that was removed from the upstream JWKS but is still usable during
+/// the grace period.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetiringKey {
+    /// The `kid` and thumbprint of the key.
+    pub key: KeyRef,
+    /// When the key was found missing upstream, in seconds since the epoch.
+    pub removed_at: u64,
+    /// When the key will be evicted, in seconds since the epoch. The key is
+    /// no longer served from that time, even if refreshes are failing.
+    pub evict_at: u64,
+}
+
+/// How refreshing the key set went, e.g. to export as metrics or to fail a
+/// readiness check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RefreshHealth {
+    /// When the key set last loaded was fetched from the identity provider,
+    /// in seconds since the epoch. For a key set loaded from a store, this is
+    /// when it was fetched by whichever refresher stored it.
+    pub last_success: u64,
+    /// When a refresh last failed, in seconds since the epoch.
+    pub last_failure: Option<u64>,
+    /// The error of the last failed refresh.
+    pub last_error: Option<String>,
+    /// How many refreshes failed since the key set was last loaded.
+    pub consecutive_failures: u32,
+    /// Whether the keys have been stale for longer than the maximum
+    /// staleness, so that no keys are served.
+    pub expired: bool,
+}
+
+impl RefreshHealth {
+    /// Whether the last refresh succeeded.
+    pub fn is_healthy(&self) -> bool {
+        self.consecutive_failures == 0
+    }
+}
+
+impl<F: JwksFetcher> RefreshingJwks<F> {
+    /// Loads the key set at `jwks_url`.
+    pub async fn from_jwks_url(
+        fetcher: F,
+        jwks_url: impl Into<String>,
+        policy: FetchPolicy,
+    ) -> Result<Self, JwksError> {
+        Self::from_jwks_url_with_store(fetcher, jwks_url, policy, (), Duration::ZERO).await
+    }
+
+    /// Discovers the `jwks_uri` in the OpenID configuration at `oidc_url`
+    /// and loads its key set. The `jwks_uri` is not looked up again on
+    /// refresh.
+    pub async fn from_oidc_url(
+        fetcher: F,
+        oidc_url: impl Into<String>,
+        policy: FetchPolicy,
+    ) -> Result<Self, JwksError> {
+        Self::from_oidc_url_with_store(fetcher, oidc_url, policy, (), Duration::ZERO).await
+    }
+}
+
+impl<F: JwksFetcher, S: JwksStore> RefreshingJwks<F, S> {
+    /// Loads the key set at `jwks_url` from `store`, or fetches it and keeps
+    /// it in `store` for `ttl`. Refreshes go through the store too, so
+    /// refreshers sharing a store fetch from the identity provider about
+    /// once per `ttl`.
+    ///
+    /// Stored key sets are checked against `policy` like fetched ones. The
+    /// store is best effort: when it fails, or holds keys the policy
+    /// refuses, the key set is fetched.
+    pub async fn from_jwks_url_with_store(
+        fetcher: F,
+        jwks_url: impl Into<String>,
+        policy: FetchPolicy,
+        store: S,
+        ttl: Duration,
+    ) -> Result<Self, JwksError> {
+        let jwks_url = policy.check_url(&jwks_url.into())?.to_string();
+        let (keys, fetched_at) = load(&fetcher, &store, &jwks_url, &policy, ttl).await?;
+        Ok(Self {
+            fetcher,
+            jwks_url,
+            policy,
+            store,
+            store_ttl: ttl,
+            grace_period: Duration::ZERO,
+            max_staleness: None,
+            keys: RwLock::new(Arc::new(keys.clone())),
+            next_eviction: AtomicU64::new(u64::MAX),
+            state: Mutex::new(State {
+                upstream: keys,
+                retiring: Vec::new(),
+                health: RefreshHealth {
+                    last_success: fetched_at,
+                    last_failure: None,
+                    last_error: None,
+                    consecutive_failures: 0,
+                    expired: false,
+                },
+            }),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        })
+    }
+
+    /// A version of [`from_jwks_url_with_store`][Self::from_jwks_url_with_store]
+    /// discovering the `jwks_uri` in the OpenID configuration at `oidc_url`.
+    /// The OpenID configuration is always fetched, and not stored.
+    pub async fn from_oidc_url_with_store(
+        fetcher: F,
+        oidc_url: impl Into<String>,
+        policy: FetchPolicy,
+        store: S,
+        ttl: Duration,
+    ) -> Result<Self, JwksError> {
+        let jwks_uri = discover_jwks_uri(&fetcher, &oidc_url.into(), &policy).await?;
+        Self::from_jwks_url_with_store(fetcher, jwks_uri, policy, store, ttl).await
+    }
+
+    /// Keeps keys removed from the upstream JWKS usable for `grace_period`,
+    /// so tokens signed shortly before a rotation still verify.
+    ///
+    /// A retiring key that shares its `kid` with a current key is only used
+    /// for tokens whose `alg` the current key does not support.
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Stops serving the keys once refreshes have been failing for longer
+    /// than `max_staleness` since the key set was last loaded: [`keys`][Self::keys]
+    /// then returns an empty, stale set, so verification fails until a
+    /// refresh succeeds.
+    ///
+    /// Without a maximum staleness, the last keys loaded are served for as
+    /// long as refreshes fail. Keys only become stale when a refresh fails,
+    /// but the staleness counts from when the last keys loaded were fetched,
+    /// so `max_staleness` should allow for a few refresh intervals and for
+    /// the store TTL.
+    pub fn with_max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = Some(max_staleness);
+        self
+    }
+
+    /// The current key set.
+    pub fn keys(&self) -> Arc<Jwks> {
+        let now = get_current_timestamp();
+        if now >= self.next_eviction.load(Ordering::Acquire) {
+            self.evict_retiring(&mut self.state(), now);
+        }
+        let keys = self
+            .keys
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        if self.is_expired(&keys) {
+            let mut expired = Jwks::from_iter([]);
+            expired.stale_since = keys.stale_since;
+            return Arc::new(expired);
+        }
+        keys
+    }
+
+    /// The url the key set is loaded from.
+    pub fn jwks_url(&self) -> &str {
+        &self.jwks_url
+    }
+
+    /// Reloads the key set and returns how the upstream JWKS changed.
+    /// Subscribers are notified when it did. On error the current key set is
+    /// kept, marked stale, and the error is recorded in the
+    /// [health][Self::health].
+    pub async fn refresh(&self) -> Result<KeySetDiff, JwksError> {
+        let loaded = load(
+            &self.fetcher,
+            &self.store,
+            &self.jwks_url,
+            &self.policy,
+            self.store_ttl,
+        )
+        .await;
+        let now = get_current_timestamp();
+        let (fetched, fetched_at) = match loaded {
+            Ok(loaded) => loaded,
+            Err(error) => {
+                self.record_failure(&error, now);
+                return Err(error);
+            }
+        };
+        let diff = {
+            let mut state = self.state();
+            state.health.last_success = fetched_at;
+            state.health.consecutive_failures = 0;
+            let diff = KeySetDiff::between(&state.upstream, &fetched);
+
+            if !self.grace_period.is_zero() {
+                let removed: Vec<Retiring> = state
+                    .upstream
+                    .iter()
+                    .filter(|(kid, jwk)| !contains(&fetched, kid, jwk))
+                    .map(|(kid, jwk)| Retiring {
+                        kid: kid.to_string(),
+                        jwk: jwk.clone(),
+                        removed_at: now,
+                    })
+                    .collect();
+                state.retiring.extend(removed);
+            }
+            let grace_period = self.grace_period.as_secs();
+            state.retiring.retain(|retiring| {
+                now < retiring.removed_at.saturating_add(grace_period)
+                    && !contains(&fetched, &retiring.kid, &retiring.jwk)
+            });
+
+            let served = serve(&fetched, &state.retiring);
+            state.upstream = fetched;
+            self.schedule_eviction(&state);
+            *self
+                .keys
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(served);
+            diff
+        };
+        if !diff.is_empty() {
+            // there may be no subscribers
+            let _ = self.events.send(diff.clone());
+        }
+        Ok(diff)
+    }
+
+    /// How refreshing went so far.
+    pub fn health(&self) -> RefreshHealth {
+        let mut health = self.state().health.clone();
+        health.expired = self.is_expired(&self.keys());
+        health
+    }
+
+    /// The keys removed from the upstream JWKS that are still usable.
+    pub fn retiring(&self) -> Vec<RetiringKey> {
+        let grace_period = self.grace_period.as_secs();
+        let mut state = self.state();
+        self.evict_retiring(&mut state, get_current_timestamp());
+        state
+            .retiring
+            .iter()
+            .map(|retiring| RetiringKey {
+                key: KeyRef::new(&retiring.kid, &retiring.jwk),
+                removed_at: retiring.removed_at,
+                evict_at: retiring.removed_at.saturating_add(grace_period),
+            })
+            .collect()
+    }
+
+    /// Subscribes to the changes found by later refreshes, e.g. to log key
+    /// rotations, alert on removed keys or invalidate caches.
+    ///
+    /// A subscriber that falls more than 16 changes behind receives
+    /// [`RecvError::Lagged`][broadcast::error::RecvError::Lagged] and misses
+    /// the oldest ones.
+    pub fn subscribe(&self) -> broadcast::Receiver<KeySetDiff> {
+        self.events.subscribe()
+    }
+
+    /// Records a failed refresh and marks the served keys stale.
+    fn record_failure(&self, error: &JwksError, now: u64) {
+        let mut state = self.state();
+        state.health.last_failure = Some(now);
+        state.health.last_error = Some(error.to_string());
+        state.health.consecutive_failures = state.health.consecutive_failures.saturating_add(1);
+
+        let mut keys = self
+            .keys
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !keys.is_stale() {
+            Arc::make_mut(&mut keys).stale_since = Some(state.health.last_success);
+        }
+    }
+
+    /// Drops the retiring keys whose grace period is over at `now`, whether
+    /// or not a refresh succeeded since, and stops serving them.
+    fn evict_retiring(&self, state: &mut State, now: u64) {
+        let grace_period = self.grace_period.as_secs();
+        let retiring = state.retiring.len();
+        state
+            .retiring
+            .retain(|retiring| now < retiring.removed_at.saturating_add(grace_period));
+        if state.retiring.len() == retiring {
+            return;
+        }
+        self.schedule_eviction(state);
+
+        let mut served = serve(&state.upstream, &state.retiring);
+        let mut keys = self
+            .keys
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        served.stale_since = keys.stale_since;
+        *keys = Arc::new(served);
+    }
+
+    /// Records when the first of the retiring keys is due for eviction.
+    fn schedule_eviction(&self, state: &State) {
+        let grace_period = self.grace_period.as_secs();
+        let next_eviction = state
+            .retiring
+            .iter()
+            .map(|retiring| retiring.removed_at.saturating_add(grace_period))
+            .min()
+            .unwrap_or(u64::MAX);
+        self.next_eviction.store(next_eviction, Ordering::Release);
+    }
+
+    /// Whether `keys` have been stale for longer than the maximum staleness.
+    fn is_expired(&self, keys: &Jwks) -> bool {
+        let Some(max_staleness) = self.max_staleness else {
+            return false;
+        };
+        keys.stale_since().is_some_and(|since| {
+            get_current_timestamp() >= since.saturating_add(max_staleness.as_secs())
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The `upstream` keys along with the `retiring` ones.
+fn serve(upstream: &Jwks, retiring: &[Retiring]) -> Jwks {
+    let mut served = upstream.clone();
+    for retiring in retiring {
+        let entry = JwkEntry {
+            kid: retiring.kid.clone(),
+            jwk: retiring.jwk.clone(),
+        };
+        served
+            .merge(Jwks::from_iter([entry]), MergeConflict::KeepBoth)
+            .expect("merging with KeepBoth does not fail");
+    }
+    served
+}
+
+/// Whether `jwks` holds the key `jwk` under `kid`.
+fn contains(jwks: &Jwks, kid: &str, jwk: &Jwk) -> bool {
+    jwks.keys_with_kid(kid).any(|key| key.params == jwk.params)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{FetchError, FetchResponse, JwksStore, MemoryStore, StoredJwks};
+
+    /// Serves whatever key set is stored in it.
+    struct StubFetcher(RefCell<serde_json::Value>);
+
+    impl JwksFetcher for StubFetcher {
+        async fn fetch(&self, _url: &str) -> Result<FetchResponse, FetchError> {
+            Ok(FetchResponse {
+                body: self.0.borrow().to_string().into_bytes(),
+                ..Default::default()
+            })
+        }
+    }
+
+    fn ec_key(kid: &str, x: &str, y: &str) -> serde_json::Value {
+        json!({ "kty": "EC", "crv": "P-256", "kid": kid, "x": x, "y": y })
+    }
+
+    #[tokio::test]
+    async fn notifies_subscribers_of_rotations() {
+        let first = ec_key(
+            "first",
+            "tOOp1Sko9BGCpfjK-mAOQF875W-xdWPnHbNtjs0xv3I",
+            "atwZ-tOsAFi6rQ1DOHzu3x6K6t8jLGAAtxwG8cIaJks",
+        );
+        // RFC 7515, appendix A.3
+        let second = ec_key(
+            "second",
+            "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+            "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0",
+        );
+        let fetcher = StubFetcher(RefCell::new(json!({ "keys": [first] })));
+        let jwks = RefreshingJwks::from_jwks_url(
+            fetcher,
+            "https://issuer.example/jwks.json",
+            FetchPolicy::default(),
+        )
+        .await
+        .unwrap();
+        let mut events = jwks.subscribe();
+
+        assert!(jwks.refresh().await.unwrap().is_empty());
+        assert!(events.try_recv().is_err());
+
+        *jwks.fetcher.0.borrow_mut() = json!({ "keys": [second] });
+        let diff = jwks.refresh().await.unwrap();
+        assert_eq!(diff.added[0].kid, "second");
+        assert_eq!(diff.removed[0].kid, "first");
+        assert_eq!(events.try_recv().unwrap(), diff);
+        assert!(jwks.keys().get("second").is_some());
+
+        // a failed refresh keeps the current keys
+        *jwks.fetcher.0.borrow_mut() = json!({ "keys": "not a list" });
+        assert!(jwks.refresh().await.is_err());
+        assert!(jwks.keys().get("second").is_some());
+    }
+
+    #[tokio::test]
+    async fn keeps_removed_keys_during_grace_period() {
+        let first = ec_key(
+            "first",
+            "tOOp1Sko9BGCpfjK-mAOQF875W-xdWPnHbNtjs0xv3I",
+            "atwZ-tOsAFi6rQ1DOHzu3x6K6t8jLGAAtxwG8cIaJks",
+        );
+        let second = ec_key(
+            "second",
+            "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+            "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0",
+        );
+        let fetcher = StubFetcher(RefCell::new(json!({ "keys": [first] })));
+        let jwks = RefreshingJwks::from_jwks_url(
+            fetcher,
+            "https://issuer.example/jwks.json",
+            FetchPolicy::default(),
+        )
+        .await
+        .unwrap()
+        .with_grace_period(Duration::from_secs(3600));
+
+        *jwks.fetcher.0.borrow_mut() = json!({ "keys": [second] });
+        let diff = jwks.refresh().await.unwrap();
+        assert_eq!(diff.removed[0].kid, "first");
+        assert!(jwks.keys().get("first").is_some());
+        assert!(jwks.keys().get("second").is_some());
+        let retiring = jwks.retiring();
+        assert_eq!(retiring.len(), 1);
+        assert_eq!(retiring[0].key.kid, "first");
+        assert_eq!(retiring[0].evict_at, retiring[0].removed_at + 3600);
+
+        // still retiring on the next refresh
+        jwks.refresh().await.unwrap();
+        assert!(jwks.keys().get("first").is_some());
+
+        // evicted once the grace period has passed
+        jwks.state().retiring[0].removed_at -= 3600;
+        jwks.refresh().await.unwrap();
+        assert!(jwks.keys().get("first").is_none());
+        assert!(jwks.retiring().is_empty());
+    }
+
+    #[tokio::test]
+    async fn evicts_retiring_keys_while_refreshes_fail() {
+        let first = ec_key(
+            "first",
+            "tOOp1Sko9BGCpfjK-mAOQF875W-xdWPnHbNtjs0xv3I",
+            "atwZ-tOsAFi6rQ1DOHzu3x6K6t8jLGAAtxwG8cIaJks",
+        );
+        let second = ec_key(
+            "second",
+            "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+            "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0",
+        );
+        let fetcher = StubFetcher(RefCell::new(json!({ "keys": [first] })));
+        let jwks = RefreshingJwks::from_jwks_url(
+            fetcher,
+            "https://issuer.example/jwks.json",
+            FetchPolicy::default(),
+        )
+        .await
+        .unwrap()
+        .with_grace_period(Duration::from_secs(3600));
+
+        *jwks.fetcher.0.borrow_mut() = json!({ "keys": [second] });
+        jwks.refresh().await.unwrap();
+        assert!(jwks.keys().get("first").is_some());
+        let evict_at = jwks.retiring()[0].evict_at;
+        assert_eq!(jwks.next_eviction.load(Ordering::Acquire), evict_at);
+
+        *jwks.fetcher.0.borrow_mut() = json!({ "keys": "not a list" });
+        jwks.state().retiring[0].removed_at -= 3600;
+        jwks.schedule_eviction(&jwks.state());
+        assert!(jwks.refresh().await.is_err());
+        let keys = jwks.keys();
+        assert!(keys.get("first").is_none());
+        assert!(keys.get("second").is_some());
+        assert!(keys.is_stale());
+        assert!(jwks.retiring().is_empty());
+        assert_eq!(jwks.next_eviction.load(Ordering::Acquire), u64::MAX);
+    }
+
+    #[tokio::test]
+    async fn shares_key_sets_through_a_store() {
+        /// Counts the requests of every refresher sharing it.
+        struct CountingFetcher(Cell<usize>);
+
+        impl JwksFetcher for CountingFetcher {
+            async fn fetch(&self, _url: &str) -> Result<FetchResponse, FetchError> {
+                self.0.set(self.0.get() + 1);
+                let first = ec_key(
+                    "first",
+                    "tOOp1Sko9BGCpfjK-mAOQF875W-xdWPnHbNtjs0xv3I",
+                    "atwZ-tOsAFi6rQ1DOHzu3x6K6t8jLGAAtxwG8cIaJks",
+                );
+                Ok(FetchResponse {
+                    body: json!({ "keys": [first] }).to_string().into_bytes(),
+                    ..Default::default()
+                })
+            }
+        }
+
+        let fetcher = CountingFetcher(Cell::new(0));
+        let store = MemoryStore::new();
+        let url = "https://issuer.example/jwks.json";
+        let ttl = Duration::from_secs(300);
+
+        let first = RefreshingJwks::from_jwks_url_with_store(
+            &fetcher,
+            url,
+            FetchPolicy::default(),
+            &store,
+            ttl,
+        )
+        .await
+        .unwrap();
+        let second = RefreshingJwks::from_jwks_url_with_store(
+            &fetcher,
+            url,
+            FetchPolicy::default(),
+            &store,
+            ttl,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fetcher.0.get(), 1);
+        assert!(second.keys().get("first").is_some());
+        first.refresh().await.unwrap();
+        second.refresh().await.unwrap();
+        assert_eq!(fetcher.0.get(), 1);
+
+        // expired entries are fetched again
+        let other_url = "https://other.example/jwks.json";
+        let expiring = RefreshingJwks::from_jwks_url_with_store(
+            &fetcher,
+            other_url,
+            FetchPolicy::default(),
+            &store,
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+        expiring.refresh().await.unwrap();
+        assert_eq!(fetcher.0.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn counts_staleness_from_when_stored_keys_were_fetched() {
+        let first = ec_key(
+            "first",
+            "tOOp1Sko9BGCpfjK-mAOQF875W-xdWPnHbNtjs0xv3I",
+            "atwZ-tOsAFi6rQ1DOHzu3x6K6t8jLGAAtxwG8cIaJks",
+        );
+        let store = MemoryStore::new();
+        let url = "https://issuer.example/jwks.json";
+        let ttl = Duration::from_secs(3600);
+        let fetched_at = get_current_timestamp() - 900;
+        let stored = StoredJwks {
+            jwks: json!({ "keys": [first] }),
+            fetched_at,
+            headers: Default::default(),
+        };
+        store.put(url, &stored, ttl).await.unwrap();
+
+        let fetcher = StubFetcher(RefCell::new(json!("not a key set")));
+        let jwks = RefreshingJwks::from_jwks_url_with_store(
+            fetcher,
+            url,
+            FetchPolicy::default(),
+            &store,
+            ttl,
+        )
+        .await
+        .unwrap()
+        .with_max_staleness(Duration::from_secs(600));
+        assert_eq!(jwks.health().last_success, fetched_at);
+
+        // a refresh served by the store keeps the original fetch time
+        jwks.refresh().await.unwrap();
+        assert_eq!(jwks.health().last_success, fetched_at);
+
+        store.put(url, &stored, Duration::ZERO).await.unwrap();
+        assert!(jwks.refresh().await.is_err());
+        assert_eq!(jwks.keys().stale_since(), Some(fetched_at));
+        assert!(jwks.health().expired);
+        assert!(jwks.keys().is_empty());
+    }
+
+    #[tokio::test]
+    async fn serves_stale_keys_until_max_staleness() {
+        let first = ec_key(
+            "first",
+            "tOOp1Sko9BGCpfjK-mAOQF875W-xdWPnHbNtjs0xv3I",
+            "atwZ-tOsAFi6rQ1DOHzu3x6K6t8jLGAAtxwG8cIaJks",
+        );
+        let fetcher = StubFetcher(RefCell::new(json!({ "keys": [first.clone()] })));
+        let jwks = RefreshingJwks::from_jwks_url(
+            fetcher,
+            "https://issuer.example/jwks.json",
+            FetchPolicy::default(),
+        )
+        .await
+        .unwrap()
+        .with_max_staleness(Duration::from_secs(600));
+        assert!(jwks.health().is_healthy());
+
+        *jwks.fetcher.0.borrow_mut() = json!("not a key set");
+        assert!(jwks.refresh().await.is_err());
+        assert!(jwks.refresh().await.is_err());
+        let health = jwks.health();
+        assert!(!health.is_healthy());
+        assert_eq!(health.consecutive_failures, 2);
+        assert!(health.last_error.is_some());
+        assert!(!health.expired);
+        let keys = jwks.keys();
+        assert!(keys.is_stale());
+        assert_eq!(keys.stale_since(), Some(health.last_success));
+        assert!(keys.get("first").is_some());
+
+        // no keys are served once the maximum staleness has passed
+        Arc::make_mut(&mut jwks.keys.write().unwrap()).stale_since =
+            Some(health.last_success - 600);
+        assert!(jwks.health().expired);
+        assert!(jwks.keys().is_empty());
+        assert!(jwks.keys().is_stale());
+
+        *jwks.fetcher.0.borrow_mut() = json!({ "keys": [first] });
+        jwks.refresh().await.unwrap();
+        let health = jwks.health();
+        assert!(health.is_healthy());
+        assert!(!health.expired);
+        assert!(health.last_error.is_some());
+        assert!(!jwks.keys().is_stale());
+        assert!(jwks.keys().get("first").is_some());
+    }
+}
@@ -0,0 +1,402 @@
+use std::str::FromStr;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, jwk, Algorithm, EncodingKey, Header};
+use p256::pkcs8::EncodePrivateKey;
+use rsa::{pkcs1::EncodeRsaPrivateKey, traits::PublicKeyParts, BigUint, RsaPrivateKey};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
+
+/// An error reading a private JWK with [`SigningJwk::from_json`].
+#[derive(Debug, Error)]
+pub enum SigningKeyError {
+    /// The key is malformed, or its members do not form a valid key pair.
+    #[error("could not read the signing key {key_id:?}: {reason}")]
+    InvalidKey {
+        key_id: Option<String>,
+        reason: String,
+    },
+
+    /// The key type, curve or `alg` cannot be used for signing.
+    #[error("unsupported signing key: {0}")]
+    UnsupportedKey(String),
+}
+
+/// A private JWK used to sign tokens.
+///
+/// Supported keys are RSA (`RS*`, `PS*`), EC P-256 (`ES256`) and P-384
+/// (`ES384`), OKP Ed25519 (`EdDSA`) and `oct` (`HS*`). When the JWK has no
+/// `alg`, RSA keys sign with `RS256`, `oct` keys with `HS256`, and EC and OKP
+/// keys with the algorithm of their curve.
+#[derive(Clone)]
+pub struct SigningJwk {
+    /// The key id.
+    pub kid: Option<String>,
+    /// The algorithm tokens are signed with.
+    pub alg: Algorithm,
+    encoding_key: EncodingKey,
+    public: jwk::Jwk,
+}
+
+/// The private JWK members needed for signing.
+#[derive(Deserialize)]
+struct PrivateJwk {
+    kty: String,
+    kid: Option<String>,
+    alg: Option<String>,
+    crv: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    d: Option<String>,
+    p: Option<String>,
+    q: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+    k: Option<String>,
+}
+
+impl Drop for PrivateJwk {
+    fn drop(&mut self) {
+        self.d.zeroize();
+        self.p.zeroize();
+        self.q.zeroize();
+        self.k.zeroize();
+    }
+}
+
+impl SigningJwk {
+    /// Parses a private JWK.
+    ///
+    /// Public members present in the JWK (`n`, `e`, `x`, `y`) must match the
+    /// ones derived from the private key.
+    pub fn from_json(jwk: &serde_json::Value) -> Result<Self, SigningKeyError> {
+        let mut jwk = PrivateJwk::deserialize(jwk).map_err(|err| SigningKeyError::InvalidKey {
+            key_id: None,
+            reason: err.to_string(),
+        })?;
+        let invalid = |reason: &str| SigningKeyError::InvalidKey {
+            key_id: jwk.kid.clone(),
+            reason: reason.to_string(),
+        };
+        let param = |value: &Option<String>, name: &str| {
+            let value = value
+                .as_deref()
+                .ok_or_else(|| invalid(&format!("missing `{name}`")))?;
+            URL_SAFE_NO_PAD
+                .decode(value)
+                .map(Zeroizing::new)
+                .map_err(|_| invalid(&format!("`{name}` is not base64url")))
+        };
+        let check_public = |value: &Option<String>, name: &str, derived: &[u8]| match value {
+            Some(_) if param(value, name)?.as_slice() != derived => {
+                Err(invalid(&format!("`{name}` does not match the private key")))
+            }
+            _ => Ok(()),
+        };
+        let declared = jwk
+            .alg
+            .as_deref()
+            .map(|alg| {
+                Algorithm::from_str(alg)
+                    .map_err(|_| SigningKeyError::UnsupportedKey(format!("algorithm {alg}")))
+            })
+            .transpose()?;
+
+        let (algorithms, encoding_key, public): (&[Algorithm], _, _) =
+            match (jwk.kty.as_str(), jwk.crv.as_deref()) {
+                ("RSA", _) => {
+                    let uint = |value, name| param(value, name).map(|b| BigUint::from_bytes_be(&b));
+                    // the primes are recovered from `d` when they are missing
+                    let primes = match (&jwk.p, &jwk.q) {
+                        (None, None) => vec![],
+                        _ => vec![uint(&jwk.p, "p")?, uint(&jwk.q, "q")?],
+                    };
+                    let key = RsaPrivateKey::from_components(
+                        uint(&jwk.n, "n")?,
+                        uint(&jwk.e, "e")?,
+                        uint(&jwk.d, "d")?,
+                        primes,
+                    )
+                    .map_err(|err| invalid(&err.to_string()))?;
+                    key.validate().map_err(|err| invalid(&err.to_string()))?;
+                    let der = key
+                        .to_pkcs1_der()
+                        .map_err(|err| invalid(&err.to_string()))?;
+                    let public = json!({
+                        "kty": "RSA",
+                        "n": URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                        "e": URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+                    });
+                    (
+                        &[
+                            Algorithm::RS256,
+                            Algorithm::RS384,
+                            Algorithm::RS512,
+                            Algorithm::PS256,
+                            Algorithm::PS384,
+                            Algorithm::PS512,
+                        ][..],
+                        EncodingKey::from_rsa_der(der.as_bytes()),
+                        public,
+                    )
+                }
+                ("EC", Some(crv @ ("P-256" | "P-384"))) => {
+                    let d = param(&jwk.d, "d")?;
+                    let (algorithms, der, point): (&[Algorithm], _, _) = if crv == "P-256" {
+                        let key = p256::SecretKey::from_slice(&d)
+                            .map_err(|_| invalid("invalid P-256 key"))?;
+                        let der = key
+                            .to_pkcs8_der()
+                            .map_err(|err| invalid(&err.to_string()))?;
+                        (&[Algorithm::ES256], der, key.public_key().to_sec1_bytes())
+                    } else {
+                        let key = p384::SecretKey::from_slice(&d)
+                            .map_err(|_| invalid("invalid P-384 key"))?;
+                        let der = key
+                            .to_pkcs8_der()
+                            .map_err(|err| invalid(&err.to_string()))?;
+                        (&[Algorithm::ES384], der, key.public_key().to_sec1_bytes())
+                    };
+                    // an uncompressed SEC1 point: 0x04 || x || y
+                    let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+                    check_public(&jwk.x, "x", x)?;
+                    check_public(&jwk.y, "y", y)?;
+                    let public = json!({
+                        "kty": "EC",
+                        "crv": crv,
+                        "x": URL_SAFE_NO_PAD.encode(x),
+                        "y": URL_SAFE_NO_PAD.encode(y),
+                    });
+                    (algorithms, EncodingKey::from_ec_der(der.as_bytes()), public)
+                }
+                ("OKP", Some("Ed25519")) => {
+                    let d = Zeroizing::new(
+                        <[u8; 32]>::try_from(param(&jwk.d, "d")?.as_slice())
+                            .map_err(|_| invalid("Ed25519 keys are 32 bytes"))?,
+                    );
+                    let key = ed25519_dalek::SigningKey::from_bytes(&d);
+                    let x = key.verifying_key().to_bytes();
+                    check_public(&jwk.x, "x", &x)?;
+                    let der = key
+                        .to_pkcs8_der()
+                        .map_err(|err| invalid(&err.to_string()))?;
+                    let public = json!({
+                        "kty": "OKP",
+                        "crv": "Ed25519",
+                        "x": URL_SAFE_NO_PAD.encode(x),
+                    });
+                    (
+                        &[Algorithm::EdDSA][..],
+                        EncodingKey::from_ed_der(der.as_bytes()),
+                        public,
+                    )
+                }
+                ("oct", _) => {
+                    let k = param(&jwk.k, "k")?;
+                    let public = json!({ "kty": "oct", "k": URL_SAFE_NO_PAD.encode(k.as_slice()) });
+                    (
+                        &[Algorithm::HS256, Algorithm::HS384, Algorithm::HS512][..],
+                        EncodingKey::from_secret(k.as_slice()),
+                        public,
+                    )
+                }
+                (kty, crv) => {
+                    return Err(SigningKeyError::UnsupportedKey(format!(
+                        "key type {kty} {}",
+                        crv.unwrap_or_default()
+                    )))
+                }
+            };
+        let alg = match declared {
+            Some(alg) if !algorithms.contains(&alg) => {
+                return Err(invalid(&format!("{alg:?} cannot be used with this key")))
+            }
+            Some(alg) => alg,
+            None => algorithms[0],
+        };
+
+        let mut public = public;
+        public["use"] = json!("sig");
+        public["alg"] = json!(alg);
+        if let Some(kid) = &jwk.kid {
+            public["kid"] = json!(kid);
+        }
+        let public = serde_json::from_value(public).map_err(|err| invalid(&err.to_string()))?;
+
+        Ok(Self {
+            kid: jwk.kid.take(),
+            alg,
+            encoding_key,
+            public,
+        })
+    }
+
+    /// The key to pass to [`jsonwebtoken::encode`].
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding_key
+    }
+
+    /// The public half of the key, with `kid`, `alg` and `use: sig`, ready
+    /// to be published in a JWKS. `None` for `oct` keys, which have no public
+    /// half.
+    pub fn public_jwk(&self) -> Option<&jwk::Jwk> {
+        match self.public.algorithm {
+            jwk::AlgorithmParameters::OctetKey(_) => None,
+            _ => Some(&self.public),
+        }
+    }
+
+    /// The JWK verifiers need, with `kid`, `alg` and `use: sig`: the public
+    /// half of the key, or for `oct` keys the secret itself, which must never
+    /// be published.
+    pub fn verifying_jwk(&self) -> &jwk::Jwk {
+        &self.public
+    }
+
+    /// A token header with the key's `alg` and `kid`.
+    pub fn header(&self) -> Header {
+        let mut header = Header::new(self.alg);
+        header.kid = self.kid.clone();
+        header
+    }
+
+    /// Signs `claims` into a JWT carrying the key's `alg` and `kid`.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        encode(&self.header(), claims, &self.encoding_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::Validation;
+    use rand::rngs::OsRng;
+    use rsa::traits::PrivateKeyParts;
+
+    use super::*;
+    use crate::Jwks;
+
+    fn b64(bytes: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    fn round_trip(key: &SigningJwk) {
+        let claims = json!({ "sub": "signed", "exp": jsonwebtoken::get_current_timestamp() + 60 });
+        let token = key.sign(&claims).unwrap();
+
+        let jwks = Jwks::from_jwk_set(jwk::JwkSet {
+            keys: vec![key.verifying_jwk().clone()],
+        })
+        .unwrap();
+        let data = jwks
+            .decode::<serde_json::Value>(&token, &Validation::new(key.alg))
+            .unwrap();
+        assert_eq!(data.header.kid, key.kid);
+        assert_eq!(data.claims["sub"], "signed");
+    }
+
+    #[test]
+    fn signs_with_rsa_keys() {
+        let private_key = RsaPrivateKey::new(&mut OsRng, 2048).unwrap();
+        let uint = |n: &BigUint| b64(&n.to_bytes_be());
+        let mut jwk = json!({
+            "kty": "RSA",
+            "kid": "rsa",
+            "alg": "PS256",
+            "n": uint(private_key.n()),
+            "e": uint(private_key.e()),
+            "d": uint(private_key.d()),
+            "p": uint(&private_key.primes()[0]),
+            "q": uint(&private_key.primes()[1]),
+        });
+        let key = SigningJwk::from_json(&jwk).unwrap();
+        assert_eq!(key.alg, Algorithm::PS256);
+        assert_eq!(
+            key.public_jwk().unwrap().common.key_algorithm,
+            Some(jwk::KeyAlgorithm::PS256)
+        );
+        round_trip(&key);
+
+        // without the primes
+        jwk.as_object_mut()
+            .unwrap()
+            .retain(|k, _| k != "p" && k != "q");
+        round_trip(&SigningJwk::from_json(&jwk).unwrap());
+    }
+
+    #[test]
+    fn signs_with_ec_okp_and_oct_keys() {
+        let secret = p256::SecretKey::random(&mut OsRng);
+        let key = SigningJwk::from_json(&json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": "ec",
+            "d": b64(&secret.to_bytes()),
+        }))
+        .unwrap();
+        assert_eq!(key.alg, Algorithm::ES256);
+        round_trip(&key);
+
+        let secret = p384::SecretKey::random(&mut OsRng);
+        let key = SigningJwk::from_json(&json!({
+            "kty": "EC",
+            "crv": "P-384",
+            "kid": "ec384",
+            "d": b64(&secret.to_bytes()),
+        }))
+        .unwrap();
+        assert_eq!(key.alg, Algorithm::ES384);
+        round_trip(&key);
+
+        let key = SigningJwk::from_json(&json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "kid": "ed",
+            "d": b64(&[7; 32]),
+        }))
+        .unwrap();
+        assert_eq!(key.alg, Algorithm::EdDSA);
+        round_trip(&key);
+
+        let key = SigningJwk::from_json(&json!({
+            "kty": "oct",
+            "kid": "hmac",
+            "alg": "HS512",
+            "k": b64(&[1; 64]),
+        }))
+        .unwrap();
+        assert!(key.public_jwk().is_none());
+        round_trip(&key);
+    }
+
+    #[test]
+    fn rejects_inconsistent_keys() {
+        let d = b64(&p256::SecretKey::random(&mut OsRng).to_bytes());
+        let other = p256::SecretKey::random(&mut OsRng)
+            .public_key()
+            .to_sec1_bytes();
+        let mismatched = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "d": d,
+            "x": b64(&other[1..33]),
+        });
+        assert!(matches!(
+            SigningJwk::from_json(&mismatched),
+            Err(SigningKeyError::InvalidKey { reason, .. }) if reason.contains("`x`")
+        ));
+
+        let wrong_alg = json!({ "kty": "EC", "crv": "P-256", "alg": "ES384", "d": d });
+        assert!(matches!(
+            SigningJwk::from_json(&wrong_alg),
+            Err(SigningKeyError::InvalidKey { .. })
+        ));
+
+        let okp = json!({ "kty": "OKP", "crv": "X25519", "d": d });
+        assert!(matches!(
+            SigningJwk::from_json(&okp),
+            Err(SigningKeyError::UnsupportedKey(_))
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use jsonwebtoken::get_current_timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    http::{fetch_checked, parse_json, parse_jwks},
+    FetchError, FetchPolicy, FetchResponse, Jwks, JwksError, JwksFetcher,
+};
+
+/// The response headers kept with a stored key set.
+const STORED_HEADERS: &[&str] = &[
+    "age",
+    "cache-control",
+    "date",
+    "etag",
+    "expires",
+    "last-modified",
+];
+
+/// A JWKS as fetched from its url, with what is needed to reuse it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredJwks {
+    /// The JWKS document.
+    pub jwks: serde_json::Value,
+    /// When the JWKS was fetched, in seconds since the epoch.
+    pub fetched_at: u64,
+    /// The cache-related headers of the JWKS response, keyed by lower-cased
+    /// header name.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// A cache of raw key sets shared between the refreshers of a fleet, so
+/// that only one replica fetches from the identity provider per TTL.
+///
+/// Entries are keyed by JWKS url. [`MemoryStore`] and [`DiskCache`][crate::DiskCache]
+/// are provided; implement this trait to keep key sets in e.g. Redis.
+///
+/// Like [`JwksFetcher`], the returned futures are not required to be `Send`.
+#[allow(async_fn_in_trait)]
+pub trait JwksStore {
+    /// Returns the key set stored for `jwks_url`, unless it has expired.
+    async fn get(&self, jwks_url: &str) -> Result<Option<StoredJwks>, FetchError>;
+
+    /// Stores the key set fetched from `jwks_url` for `ttl`.
+    async fn put(&self, jwks_url: &str, jwks: &StoredJwks, ttl: Duration)
+        -> Result<(), FetchError>;
+}
+
+/// A store that keeps nothing, used when no store is configured.
+impl JwksStore for () {
+    async fn get(&self, _jwks_url: &str) -> Result<Option<StoredJwks>, FetchError> {
+        Ok(None)
+    }
+
+    async fn put(
+        &self,
+        _jwks_url: &str,
+        _jwks: &StoredJwks,
+        _ttl: Duration,
+    ) -> Result<(), FetchError> {
+        Ok(())
+    }
+}
+
+impl<T: JwksStore + ?Sized> JwksStore for &T {
+    async fn get(&self, jwks_url: &str) -> Result<Option<StoredJwks>, FetchError> {
+        (**self).get(jwks_url).await
+    }
+
+    async fn put(
+        &self,
+        jwks_url: &str,
+        jwks: &StoredJwks,
+        ttl: Duration,
+    ) -> Result<(), FetchError> {
+        (**self).put(jwks_url, jwks, ttl).await
+    }
+}
+
+impl<T: JwksStore + ?Sized> JwksStore for Arc<T> {
+    async fn get(&self, jwks_url: &str) -> Result<Option<StoredJwks>, FetchError> {
+        (**self).get(jwks_url).await
+    }
+
+    async fn put(
+        &self,
+        jwks_url: &str,
+        jwks: &StoredJwks,
+        ttl: Duration,
+    ) -> Result<(), FetchError> {
+        (**self).put(jwks_url, jwks, ttl).await
+    }
+}
+
+/// A [`JwksStore`] in process memory, e.g. shared by the refreshers of
+/// several issuers or used in tests.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    /// The stored key sets and when they expire.
+    entries: Mutex<HashMap<String, (StoredJwks, u64)>>,
+}
+
+impl MemoryStore {
+    /// An empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl JwksStore for MemoryStore {
+    async fn get(&self, jwks_url: &str) -> Result<Option<StoredJwks>, FetchError> {
+        let entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(entries
+            .get(jwks_url)
+            .filter(|(_, expires_at)| get_current_timestamp() < *expires_at)
+            .map(|(jwks, _)| jwks.clone()))
+    }
+
+    async fn put(
+        &self,
+        jwks_url: &str,
+        jwks: &StoredJwks,
+        ttl: Duration,
+    ) -> Result<(), FetchError> {
+        let expires_at = get_current_timestamp().saturating_add(ttl.as_secs());
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(jwks_url.to_string(), (jwks.clone(), expires_at));
+        Ok(())
+    }
+}
+
+impl StoredJwks {
+    pub(crate) fn new(response: &FetchResponse) -> Result<Self, JwksError> {
+        Ok(Self {
+            jwks: parse_json(response)?,
+            fetched_at: get_current_timestamp(),
+            headers: STORED_HEADERS
+                .iter()
+                .filter_map(|name| Some((name.to_string(), response.header(name)?.to_string())))
+                .collect(),
+        })
+    }
+
+    /// Builds the key set, checking its keys against `policy`.
+    pub(crate) fn to_jwks(&self, policy: &FetchPolicy) -> Result<Jwks, JwksError> {
+        let response = FetchResponse {
+            body: self.jwks.to_string().into_bytes(),
+            headers: self.headers.clone(),
+            url: None,
+        };
+        parse_jwks(&response, &policy.keys)
+    }
+}
+
+/// Fetches and parses the JWKS at `jwks_url`, keeping what to store.
+pub(crate) async fn fetch(
+    client: &impl JwksFetcher,
+    jwks_url: &str,
+    policy: &FetchPolicy,
+) -> Result<(Jwks, StoredJwks), JwksError> {
+    let response = fetch_checked(client, jwks_url, policy).await?;
+    let jwks = parse_jwks(&response, &policy.keys)?;
+    Ok((jwks, StoredJwks::new(&response)?))
+}
+
+/// Loads the key set at `jwks_url` from `store`, or fetches it and stores it
+/// for `ttl`. Returns it along with when it was fetched.
+///
+/// Store errors, and stored key sets the policy refuses, fall through to
+/// fetching.
+pub(crate) async fn load(
+    client: &impl JwksFetcher,
+    store: &impl JwksStore,
+    jwks_url: &str,
+    policy: &FetchPolicy,
+    ttl: Duration,
+) -> Result<(Jwks, u64), JwksError> {
+    if let Ok(Some(stored)) = store.get(jwks_url).await {
+        if let Ok(jwks) = stored.to_jwks(policy) {
+            return Ok((jwks, stored.fetched_at));
+        }
+    }
+    let (jwks, stored) = fetch(client, jwks_url, policy).await?;
+    let _ = store.put(jwks_url, &stored, ttl).await;
+    Ok((jwks, stored.fetched_at))
+}
@@ -0,0 +1,386 @@
+//! Helpers for testing code that fetches keys and verifies tokens, without a
+//! real identity provider.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use jsonwebtoken::{encode, get_current_timestamp, jwk, Algorithm, Header};
+use serde_json::json;
+
+use crate::{GeneratedKey, KeyGenerator};
+
+/// How a [`MockIssuer`] misbehaves, see [`MockIssuer::set_fault`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Answer every request with this HTTP status and an empty body, e.g.
+    /// `503` to simulate an outage.
+    Status(u16),
+    /// Wait this long before answering normally.
+    Delay(Duration),
+    /// Answer with a `200` whose body is not valid JSON.
+    InvalidJson,
+}
+
+/// An in-process OpenID provider for tests.
+///
+/// The issuer listens on a random port of `127.0.0.1`, serves its OpenID
+/// configuration at `/.well-known/openid-configuration` and its JWKS at
+/// `/jwks.json`, and signs tokens with its current key. The server stops when
+/// the issuer is dropped.
+///
+/// Loopback urls are refused by [`FetchPolicy::strict`][crate::FetchPolicy::strict];
+/// set `allow_insecure_localhost` to test against the issuer with it. `HS*`
+/// issuers publish their secrets, which fetched key sets only accept with
+/// `keys.allow_symmetric`.
+pub struct MockIssuer {
+    url: String,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<AtomicBool>,
+    server: Option<JoinHandle<()>>,
+}
+
+struct State {
+    alg: Algorithm,
+    /// The published keys, the last one signing new tokens.
+    keys: Vec<GeneratedKey>,
+    fault: Option<Fault>,
+    jwks_requests: usize,
+}
+
+impl MockIssuer {
+    /// Starts an issuer with a single `ES256` key.
+    pub fn start() -> io::Result<Self> {
+        Self::start_with(Algorithm::ES256)
+    }
+
+    /// Starts an issuer signing with `alg` keys.
+    pub fn start_with(alg: Algorithm) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let state = Arc::new(Mutex::new(State {
+            alg,
+            keys: vec![generate(alg)?],
+            fault: None,
+            jwks_requests: 0,
+        }));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let server = {
+            let (url, state, shutdown) = (url.clone(), state.clone(), shutdown.clone());
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else { continue };
+                    let (url, state) = (url.clone(), state.clone());
+                    // requests are answered concurrently so a delayed
+                    // response does not hold up the others
+                    thread::spawn(move || {
+                        let _ = respond(stream, &url, &state);
+                    });
+                }
+            })
+        };
+
+        Ok(Self {
+            url,
+            state,
+            shutdown,
+            server: Some(server),
+        })
+    }
+
+    /// The issuer url, e.g. `http://127.0.0.1:50123`.
+    pub fn issuer(&self) -> &str {
+        &self.url
+    }
+
+    /// The url of the OpenID configuration document.
+    pub fn oidc_url(&self) -> String {
+        format!("{}/.well-known/openid-configuration", self.url)
+    }
+
+    /// The url of the JWKS.
+    pub fn jwks_uri(&self) -> String {
+        format!("{}/jwks.json", self.url)
+    }
+
+    /// The `kid` of the key signing new tokens.
+    pub fn current_kid(&self) -> String {
+        let state = self.state();
+        let key = state.keys.last().expect("the issuer always has a key");
+        key.signing_key
+            .kid
+            .clone()
+            .expect("generated keys have a kid")
+    }
+
+    /// The currently published keys.
+    pub fn jwk_set(&self) -> jwk::JwkSet {
+        jwk_set(&self.state())
+    }
+
+    /// Generates a new signing key and returns its `kid`. The previous keys
+    /// stay published until [removed][Self::remove_key].
+    pub fn rotate(&self) -> io::Result<String> {
+        let mut state = self.state();
+        let key = generate(state.alg)?;
+        let kid = key
+            .signing_key
+            .kid
+            .clone()
+            .expect("generated keys have a kid");
+        state.keys.push(key);
+        Ok(kid)
+    }
+
+    /// Stops publishing the key `kid`. The signing key cannot be removed.
+    pub fn remove_key(&self, kid: &str) {
+        let mut state = self.state();
+        let current = state.keys.len() - 1;
+        let mut index = 0;
+        state.keys.retain(|key| {
+            let keep = index == current || key.signing_key.kid.as_deref() != Some(kid);
+            index += 1;
+            keep
+        });
+    }
+
+    /// Makes every following response misbehave, or restores normal
+    /// responses with `None`.
+    pub fn set_fault(&self, fault: Option<Fault>) {
+        self.state().fault = fault;
+    }
+
+    /// How many times the JWKS has been requested.
+    pub fn jwks_requests(&self) -> usize {
+        self.state().jwks_requests
+    }
+
+    /// Signs a token with the current key. `claims` are added to, and
+    /// override, default `iss`, `sub`, `iat` and `exp` (in one hour) claims.
+    pub fn token(&self, claims: serde_json::Value) -> String {
+        let now = get_current_timestamp();
+        let mut all = json!({
+            "iss": self.url,
+            "sub": "mock-subject",
+            "iat": now,
+            "exp": now + 3600,
+        });
+        if let Some(claims) = claims.as_object() {
+            for (name, value) in claims {
+                all[name] = value.clone();
+            }
+        }
+        let state = self.state();
+        let key = &state
+            .keys
+            .last()
+            .expect("the issuer always has a key")
+            .signing_key;
+        key.sign(&all).expect("signing with a generated key")
+    }
+
+    /// Signs `claims` as they are with the current key and an arbitrary
+    /// header, e.g. one with a wrong `kid` or `alg`.
+    pub fn token_with_header(
+        &self,
+        header: &Header,
+        claims: &serde_json::Value,
+    ) -> jsonwebtoken::errors::Result<String> {
+        let state = self.state();
+        let key = &state
+            .keys
+            .last()
+            .expect("the issuer always has a key")
+            .signing_key;
+        encode(header, claims, key.encoding_key())
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+impl Drop for MockIssuer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // wake the accept loop up so it sees the flag
+        let _ = TcpStream::connect(self.url.trim_start_matches("http://"));
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn generate(alg: Algorithm) -> io::Result<GeneratedKey> {
+    KeyGenerator::new(alg)
+        .generate()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+fn jwk_set(state: &State) -> jwk::JwkSet {
+    jwk::JwkSet {
+        keys: state
+            .keys
+            .iter()
+            .map(|key| key.verifying_jwk().clone())
+            .collect(),
+    }
+}
+
+fn respond(stream: TcpStream, url: &str, state: &Mutex<State>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    // skip the headers, the request has no body
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let (fault, body) = {
+        let mut state = lock(state);
+        let body = match path {
+            "/.well-known/openid-configuration" => Some(json!({
+                "issuer": url,
+                "jwks_uri": format!("{url}/jwks.json"),
+                "id_token_signing_alg_values_supported": [state.alg],
+            })),
+            "/jwks.json" => {
+                state.jwks_requests += 1;
+                Some(serde_json::to_value(jwk_set(&state)).expect("JWKS serialize"))
+            }
+            _ => None,
+        };
+        (state.fault.clone(), body)
+    };
+
+    let (status, body) = match (fault, body) {
+        (_, None) => (404, String::new()),
+        (Some(Fault::Status(status)), _) => (status, String::new()),
+        (Some(Fault::InvalidJson), _) => (200, "{\"keys\": [".to_string()),
+        (Some(Fault::Delay(delay)), Some(body)) => {
+            thread::sleep(delay);
+            (200, body.to_string())
+        }
+        (None, Some(body)) => (200, body.to_string()),
+    };
+
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        if status == 200 { "OK" } else { "Error" },
+        body.len(),
+    )?;
+    stream.flush()
+}
+
+#[cfg(all(test, feature = "reqwest"))]
+mod tests {
+    use jsonwebtoken::Validation;
+
+    use super::*;
+    use crate::{Jwks, JwksError, VerifyError};
+
+    fn validation(issuer: &MockIssuer) -> Validation {
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.set_issuer(&[issuer.issuer()]);
+        validation
+    }
+
+    #[tokio::test]
+    async fn serves_discovery_and_signs_tokens() {
+        let issuer = MockIssuer::start().unwrap();
+        let jwks = Jwks::from_oidc_url(issuer.oidc_url()).await.unwrap();
+        assert_eq!(jwks.len(), 1);
+        assert_eq!(issuer.jwks_requests(), 1);
+
+        let token = issuer.token(json!({ "sub": "alice", "role": "admin" }));
+        let data = jwks
+            .decode::<serde_json::Value>(&token, &validation(&issuer))
+            .unwrap();
+        assert_eq!(data.claims["sub"], "alice");
+        assert_eq!(data.claims["role"], "admin");
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some("unknown".to_string());
+        let token = issuer
+            .token_with_header(&header, &json!({ "sub": "mallory" }))
+            .unwrap();
+        assert!(matches!(
+            jwks.decode::<serde_json::Value>(&token, &validation(&issuer)),
+            Err(VerifyError::UnknownKey)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rotates_keys() {
+        let issuer = MockIssuer::start().unwrap();
+        let old_kid = issuer.current_kid();
+        let old_token = issuer.token(json!({}));
+
+        let new_kid = issuer.rotate().unwrap();
+        assert_ne!(old_kid, new_kid);
+        let jwks = Jwks::from_jwks_url(issuer.jwks_uri()).await.unwrap();
+        assert_eq!(jwks.len(), 2);
+        jwks.decode::<serde_json::Value>(&old_token, &validation(&issuer))
+            .unwrap();
+
+        issuer.remove_key(&old_kid);
+        issuer.remove_key(&new_kid);
+        let jwks = Jwks::from_jwks_url(issuer.jwks_uri()).await.unwrap();
+        assert!(jwks.get(&old_kid).is_none());
+        assert!(jwks.get(&new_kid).is_some());
+        jwks.decode::<serde_json::Value>(&issuer.token(json!({})), &validation(&issuer))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn simulates_faults() {
+        let issuer = MockIssuer::start().unwrap();
+
+        issuer.set_fault(Some(Fault::Status(503)));
+        assert!(matches!(
+            Jwks::from_jwks_url(issuer.jwks_uri()).await,
+            Err(JwksError::FetchError(_))
+        ));
+
+        issuer.set_fault(Some(Fault::InvalidJson));
+        assert!(matches!(
+            Jwks::from_jwks_url(issuer.jwks_uri()).await,
+            Err(JwksError::FetchError(_))
+        ));
+
+        issuer.set_fault(Some(Fault::Delay(Duration::from_millis(300))));
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        assert!(Jwks::from_jwks_url_with_client(&client, issuer.jwks_uri())
+            .await
+            .is_err());
+
+        issuer.set_fault(None);
+        Jwks::from_jwks_url_with_client(&client, issuer.jwks_uri())
+            .await
+            .unwrap();
+    }
+}
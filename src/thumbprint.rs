@@ -0,0 +1,65 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk;
+use sha2::{Digest, Sha256};
+
+use crate::{curve_name, Jwk};
+
+impl Jwk {
+    /// The base64url RFC 7638 SHA-256 thumbprint of the key, which identifies
+    /// its key material independently of its `kid`.
+    pub fn thumbprint(&self) -> String {
+        thumbprint(&self.params)
+    }
+}
+
+/// The RFC 7638 thumbprint of a key: the SHA-256 of its required members,
+/// serialized in lexicographic order without whitespace.
+pub(crate) fn thumbprint(params: &jwk::AlgorithmParameters) -> String {
+    let canonical = match params {
+        jwk::AlgorithmParameters::RSA(params) => {
+            format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, params.e, params.n)
+        }
+        jwk::AlgorithmParameters::EllipticCurve(params) => format!(
+            r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+            curve_name(&params.curve),
+            params.x,
+            params.y
+        ),
+        jwk::AlgorithmParameters::OctetKeyPair(params) => format!(
+            r#"{{"crv":"{}","kty":"OKP","x":"{}"}}"#,
+            curve_name(&params.curve),
+            params.x
+        ),
+        jwk::AlgorithmParameters::OctetKey(params) => {
+            format!(r#"{{"k":"{}","kty":"oct"}}"#, params.value)
+        }
+    };
+    URL_SAFE_NO_PAD.encode(Sha256::digest(canonical))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::Jwks;
+
+    #[test]
+    fn computes_rfc7638_thumbprint() {
+        // RFC 7638, section 3.1
+        let jwks = Jwks::from_jwk_set(
+            serde_json::from_value(json!({ "keys": [{
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29"
+            }] }))
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            jwks.get("2011-04-29").unwrap().thumbprint(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
+}
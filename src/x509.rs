@@ -0,0 +1,881 @@
+use std::time::SystemTime;
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use const_oid::db::{rfc5912, rfc8410};
+use jsonwebtoken::jwk;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use thiserror::Error;
+use x509_cert::{
+    der::{Decode, Encode},
+    ext::pkix::BasicConstraints,
+    spki::SubjectPublicKeyInfoOwned,
+    Certificate,
+};
+
+use crate::pem::{public_key_params, PemError, PublicKeyParams};
+
+/// Rules for validating the `x5c` certificate chain published with a key.
+///
+/// When set on a [`KeyPolicy`][crate::KeyPolicy], every key carrying an `x5c`
+/// chain must chain up to one of the trust anchors, every certificate must be
+/// within its validity period, and the leaf certificate's public key must
+/// match the key's own parameters.
+#[derive(Clone, Debug)]
+pub struct X5cPolicy {
+    trust_anchors: Vec<Certificate>,
+    /// Reject keys that do not carry an `x5c` chain at all.
+    pub require_x5c: bool,
+    /// Check the `notBefore` / `notAfter` dates of every certificate.
+    pub check_validity: bool,
+    /// The time to check validity dates against. Defaults to the current time.
+    pub validation_time: Option<SystemTime>,
+}
+
+/// An error validating the `x5c` certificate chain of a key.
+#[derive(Clone, Debug, Error)]
+pub enum X5cError {
+    /// A certificate is not valid base64 or DER.
+    #[error("invalid certificate encoding: {0}")]
+    InvalidEncoding(String),
+
+    /// The key has no `x5c` chain but the policy requires one.
+    #[error("the key does not carry an x5c certificate chain")]
+    MissingChain,
+
+    /// The `x5c` array is empty.
+    #[error("the x5c certificate chain is empty")]
+    EmptyChain,
+
+    /// A certificate is expired or not yet valid.
+    #[error("certificate {subject:?} is outside its validity period")]
+    OutsideValidity { subject: String },
+
+    /// A certificate in the chain is not signed by the next one.
+    #[error("certificate {subject:?} has an invalid signature")]
+    InvalidSignature { subject: String },
+
+    /// A certificate used to sign another one is not a CA certificate.
+    #[error("certificate {subject:?} is not a CA certificate")]
+    NotCa { subject: String },
+
+    /// The chain does not end at a configured trust anchor.
+    #[error("the certificate chain does not lead to a trusted root")]
+    UntrustedChain,
+
+    /// A key or signature algorithm in the chain is not supported.
+    #[error("unsupported algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+
+    /// The leaf certificate's public key differs from the key parameters.
+    #[error("the leaf certificate public key does not match the key parameters")]
+    KeyMismatch,
+
+    /// The `x5t` or `x5t#S256` thumbprint does not match the leaf certificate.
+    #[error("the {0} thumbprint does not match the leaf certificate")]
+    ThumbprintMismatch(&'static str),
+}
+
+impl From<PemError> for X5cError {
+    fn from(err: PemError) -> Self {
+        match err {
+            PemError::InvalidEncoding(reason) => X5cError::InvalidEncoding(reason),
+            PemError::UnsupportedKeyType(key_type) => X5cError::UnsupportedAlgorithm(key_type),
+        }
+    }
+}
+
+impl X5cPolicy {
+    /// Creates a policy trusting the given DER encoded root certificates.
+    pub fn from_der<I, C>(trust_anchors: I) -> Result<Self, X5cError>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<[u8]>,
+    {
+        let trust_anchors = trust_anchors
+            .into_iter()
+            .map(|der| parse_der(der.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::with_anchors(trust_anchors))
+    }
+
+    /// Creates a policy trusting every certificate in a PEM bundle.
+    pub fn from_pem(pem: &[u8]) -> Result<Self, X5cError> {
+        let trust_anchors = Certificate::load_pem_chain(pem)
+            .map_err(|err| X5cError::InvalidEncoding(err.to_string()))?;
+        Ok(Self::with_anchors(trust_anchors))
+    }
+
+    fn with_anchors(trust_anchors: Vec<Certificate>) -> Self {
+        Self {
+            trust_anchors,
+            require_x5c: false,
+            check_validity: true,
+            validation_time: None,
+        }
+    }
+
+    /// Validates the `x5c` chain of `jwk` against this policy.
+    pub fn validate(&self, jwk: &jwk::Jwk) -> Result<(), X5cError> {
+        let Some(x5c) = &jwk.common.x509_chain else {
+            return if self.require_x5c {
+                Err(X5cError::MissingChain)
+            } else {
+                Ok(())
+            };
+        };
+        let chain = parse_chain(x5c)?;
+        self.validate_chain(&chain)?;
+        check_key_matches(&chain[0], &jwk.algorithm)
+    }
+
+    fn validate_chain(&self, chain: &[Certificate]) -> Result<(), X5cError> {
+        if self.check_validity {
+            let now = self.validation_time.unwrap_or_else(SystemTime::now);
+            for cert in chain {
+                check_validity(cert, now)?;
+            }
+        }
+
+        for pair in chain.windows(2) {
+            check_issued_by(&pair[0], &pair[1])?;
+        }
+
+        let last = chain.last().ok_or(X5cError::EmptyChain)?;
+        if self.trust_anchors.contains(last) {
+            return Ok(());
+        }
+        let anchor = self
+            .trust_anchors
+            .iter()
+            .find(|anchor| anchor.tbs_certificate.subject == last.tbs_certificate.issuer)
+            .ok_or(X5cError::UntrustedChain)?;
+        check_issued_by(last, anchor).map_err(|_| X5cError::UntrustedChain)
+    }
+}
+
+fn parse_der(der: &[u8]) -> Result<Certificate, X5cError> {
+    Certificate::from_der(der).map_err(|err| X5cError::InvalidEncoding(err.to_string()))
+}
+
+/// Decodes an `x5c` array. Per RFC 7517 its entries are standard (not
+/// url-safe) base64 DER certificates, leaf first.
+fn parse_chain(x5c: &[String]) -> Result<Vec<Certificate>, X5cError> {
+    if x5c.is_empty() {
+        return Err(X5cError::EmptyChain);
+    }
+    x5c.iter()
+        .map(|cert| {
+            let der = STANDARD
+                .decode(cert)
+                .map_err(|err| X5cError::InvalidEncoding(err.to_string()))?;
+            parse_der(&der)
+        })
+        .collect()
+}
+
+/// The `x5t` and `x5t#S256` thumbprints of a key's leaf certificate.
+pub(crate) struct Thumbprints {
+    pub(crate) sha1: String,
+    pub(crate) sha256: String,
+}
+
+/// Computes the thumbprints of the `x5c` leaf certificate of `jwk` and checks
+/// them against the published `x5t` and `x5t#S256` values, if any.
+///
+/// Returns `None` for keys without an `x5c` chain.
+pub(crate) fn check_thumbprints(jwk: &jwk::Jwk) -> Result<Option<Thumbprints>, X5cError> {
+    let Some(leaf) = jwk.common.x509_chain.as_ref().and_then(|x5c| x5c.first()) else {
+        return Ok(None);
+    };
+    let der = STANDARD
+        .decode(leaf)
+        .map_err(|err| X5cError::InvalidEncoding(err.to_string()))?;
+    let thumbprints = Thumbprints {
+        sha1: URL_SAFE_NO_PAD.encode(Sha1::digest(&der)),
+        sha256: URL_SAFE_NO_PAD.encode(Sha256::digest(&der)),
+    };
+
+    if let Some(x5t) = &jwk.common.x509_sha1_fingerprint {
+        if *x5t != thumbprints.sha1 {
+            return Err(X5cError::ThumbprintMismatch("x5t"));
+        }
+    }
+    if let Some(x5t_s256) = &jwk.common.x509_sha256_fingerprint {
+        if *x5t_s256 != thumbprints.sha256 {
+            return Err(X5cError::ThumbprintMismatch("x5t#S256"));
+        }
+    }
+    Ok(Some(thumbprints))
+}
+
+fn subject(cert: &Certificate) -> String {
+    cert.tbs_certificate.subject.to_string()
+}
+
+fn check_validity(cert: &Certificate, now: SystemTime) -> Result<(), X5cError> {
+    let validity = &cert.tbs_certificate.validity;
+    if now < validity.not_before.to_system_time() || now > validity.not_after.to_system_time() {
+        return Err(X5cError::OutsideValidity {
+            subject: subject(cert),
+        });
+    }
+    Ok(())
+}
+
+fn check_issued_by(cert: &Certificate, issuer: &Certificate) -> Result<(), X5cError> {
+    if cert.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+        return Err(X5cError::InvalidSignature {
+            subject: subject(cert),
+        });
+    }
+    let is_ca = issuer
+        .tbs_certificate
+        .get::<BasicConstraints>()
+        .ok()
+        .flatten()
+        .is_some_and(|(_, constraints)| constraints.ca);
+    if !is_ca {
+        return Err(X5cError::NotCa {
+            subject: subject(issuer),
+        });
+    }
+    verify_signature(cert, &issuer.tbs_certificate.subject_public_key_info)
+}
+
+fn verify_signature(
+    cert: &Certificate,
+    issuer_key: &SubjectPublicKeyInfoOwned,
+) -> Result<(), X5cError> {
+    let tbs = cert
+        .tbs_certificate
+        .to_der()
+        .map_err(|err| X5cError::InvalidEncoding(err.to_string()))?;
+    let signature = cert.signature.raw_bytes();
+    let invalid = || X5cError::InvalidSignature {
+        subject: subject(cert),
+    };
+
+    let algorithm = cert.signature_algorithm.oid;
+    let verified = match public_key_params(issuer_key)? {
+        PublicKeyParams::Rsa { n, e } => {
+            let key = RsaPublicKey::new(
+                rsa::BigUint::from_bytes_be(&n),
+                rsa::BigUint::from_bytes_be(&e),
+            )
+            .map_err(|_| invalid())?;
+            let (scheme, hashed) = match algorithm {
+                rfc5912::SHA_256_WITH_RSA_ENCRYPTION => {
+                    (Pkcs1v15Sign::new::<Sha256>(), Sha256::digest(&tbs).to_vec())
+                }
+                rfc5912::SHA_384_WITH_RSA_ENCRYPTION => {
+                    (Pkcs1v15Sign::new::<Sha384>(), Sha384::digest(&tbs).to_vec())
+                }
+                rfc5912::SHA_512_WITH_RSA_ENCRYPTION => {
+                    (Pkcs1v15Sign::new::<Sha512>(), Sha512::digest(&tbs).to_vec())
+                }
+                other => return Err(X5cError::UnsupportedAlgorithm(other.to_string())),
+            };
+            key.verify(scheme, &hashed, signature).is_ok()
+        }
+        PublicKeyParams::Ec { curve, x, y } => {
+            let hashed = match algorithm {
+                rfc5912::ECDSA_WITH_SHA_256 => Sha256::digest(&tbs).to_vec(),
+                rfc5912::ECDSA_WITH_SHA_384 => Sha384::digest(&tbs).to_vec(),
+                other => return Err(X5cError::UnsupportedAlgorithm(other.to_string())),
+            };
+            let point = [&[0x04], x.as_slice(), y.as_slice()].concat();
+            verify_ecdsa(curve, &point, &hashed, signature).ok_or_else(invalid)?
+        }
+        PublicKeyParams::Ed25519 { x } => {
+            if algorithm != rfc8410::ID_ED_25519 {
+                return Err(X5cError::UnsupportedAlgorithm(algorithm.to_string()));
+            }
+            let key = ed25519_dalek::VerifyingKey::try_from(x.as_slice()).map_err(|_| invalid())?;
+            let signature =
+                ed25519_dalek::Signature::from_slice(signature).map_err(|_| invalid())?;
+            key.verify_strict(&tbs, &signature).is_ok()
+        }
+    };
+
+    if verified {
+        Ok(())
+    } else {
+        Err(invalid())
+    }
+}
+
+fn verify_ecdsa(
+    curve: jwk::EllipticCurve,
+    point: &[u8],
+    hashed: &[u8],
+    sig: &[u8],
+) -> Option<bool> {
+    use p256::ecdsa::signature::hazmat::PrehashVerifier;
+
+    match curve {
+        jwk::EllipticCurve::P256 => {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(point).ok()?;
+            let sig = p256::ecdsa::DerSignature::try_from(sig).ok()?;
+            Some(key.verify_prehash(hashed, &sig).is_ok())
+        }
+        jwk::EllipticCurve::P384 => {
+            let key = p384::ecdsa::VerifyingKey::from_sec1_bytes(point).ok()?;
+            let sig = p384::ecdsa::DerSignature::try_from(sig).ok()?;
+            Some(key.verify_prehash(hashed, &sig).is_ok())
+        }
+        _ => None,
+    }
+}
+
+/// Fills in public key parameters missing from a JWK (absent or empty) with
+/// the public key of the leaf certificate in its `x5c` chain. With `replace`,
+/// parameters that differ from the certificate are overwritten too.
+///
+/// Returns whether anything was changed. Keys whose `kty` does not match the
+/// certificate are left untouched.
+pub(crate) fn fill_params_from_x5c(key: &mut serde_json::Value, replace: bool) -> bool {
+    let Some(object) = key.as_object_mut() else {
+        return false;
+    };
+    let Some(leaf) = object
+        .get("x5c")
+        .and_then(|x5c| x5c.get(0))
+        .and_then(serde_json::Value::as_str)
+    else {
+        return false;
+    };
+    let Ok(chain) = parse_chain(&[leaf.to_string()]) else {
+        return false;
+    };
+    let Ok(params) = public_key_params(&chain[0].tbs_certificate.subject_public_key_info) else {
+        return false;
+    };
+
+    let encode = |bytes: &[u8]| serde_json::Value::from(URL_SAFE_NO_PAD.encode(bytes));
+    let fields = match params {
+        PublicKeyParams::Rsa { n, e } => vec![
+            ("kty", serde_json::Value::from("RSA")),
+            ("n", encode(&n)),
+            ("e", encode(&e)),
+        ],
+        PublicKeyParams::Ec { curve, x, y } => vec![
+            ("kty", serde_json::Value::from("EC")),
+            ("crv", serde_json::json!(curve)),
+            ("x", encode(&x)),
+            ("y", encode(&y)),
+        ],
+        PublicKeyParams::Ed25519 { x } => vec![
+            ("kty", serde_json::Value::from("OKP")),
+            ("crv", serde_json::Value::from("Ed25519")),
+            ("x", encode(&x)),
+        ],
+    };
+    if object.get("kty").is_some_and(|kty| *kty != fields[0].1) {
+        return false;
+    }
+
+    let mut filled = false;
+    for (name, value) in fields {
+        let update = match object.get(name) {
+            None => true,
+            Some(existing) if existing.as_str().is_some_and(str::is_empty) => true,
+            Some(existing) => replace && *existing != value,
+        };
+        if update {
+            object.insert(name.to_string(), value);
+            filled = true;
+        }
+    }
+    filled
+}
+
+/// Returns a copy of `jwk` with public key parameters taken from its `x5c`
+/// leaf certificate, or `None` if there is nothing to change.
+///
+/// Empty parameters are always filled in. Parameters that are present but
+/// differ from the leaf certificate, e.g. left stale by a key rotation, are
+/// only replaced when `policy` validates the chain: an unvalidated
+/// certificate never overrides the key's own parameters.
+pub(crate) fn with_params_from_x5c(jwk: &jwk::Jwk, policy: Option<&X5cPolicy>) -> Option<jwk::Jwk> {
+    let x5c = jwk.common.x509_chain.as_ref()?;
+    let has_empty_params = match &jwk.algorithm {
+        jwk::AlgorithmParameters::RSA(params) => params.n.is_empty() || params.e.is_empty(),
+        jwk::AlgorithmParameters::EllipticCurve(params) => {
+            params.x.is_empty() || params.y.is_empty()
+        }
+        jwk::AlgorithmParameters::OctetKeyPair(params) => params.x.is_empty(),
+        jwk::AlgorithmParameters::OctetKey(_) => false,
+    };
+    let stale_params = !has_empty_params
+        && policy.is_some_and(|policy| {
+            parse_chain(x5c).is_ok_and(|chain| {
+                policy.validate_chain(&chain).is_ok()
+                    && check_key_matches(&chain[0], &jwk.algorithm).is_err()
+            })
+        });
+    if !has_empty_params && !stale_params {
+        return None;
+    }
+
+    let mut value = serde_json::to_value(jwk).ok()?;
+    if !fill_params_from_x5c(&mut value, stale_params) {
+        return None;
+    }
+    serde_json::from_value(value).ok()
+}
+
+fn check_key_matches(
+    leaf: &Certificate,
+    params: &jwk::AlgorithmParameters,
+) -> Result<(), X5cError> {
+    let leaf_key = public_key_params(&leaf.tbs_certificate.subject_public_key_info)?;
+    let decode = |value: &str| URL_SAFE_NO_PAD.decode(value).ok();
+
+    let matches = match (params, leaf_key) {
+        (jwk::AlgorithmParameters::RSA(jwk), PublicKeyParams::Rsa { n, e }) => {
+            decode(&jwk.n).is_some_and(|jwk_n| trim_leading_zeros(&jwk_n) == trim_leading_zeros(&n))
+                && decode(&jwk.e)
+                    .is_some_and(|jwk_e| trim_leading_zeros(&jwk_e) == trim_leading_zeros(&e))
+        }
+        (jwk::AlgorithmParameters::EllipticCurve(jwk), PublicKeyParams::Ec { curve, x, y }) => {
+            jwk.curve == curve
+                && decode(&jwk.x).is_some_and(|jwk_x| jwk_x == x)
+                && decode(&jwk.y).is_some_and(|jwk_y| jwk_y == y)
+        }
+        (jwk::AlgorithmParameters::OctetKeyPair(jwk), PublicKeyParams::Ed25519 { x }) => {
+            jwk.curve == jwk::EllipticCurve::Ed25519
+                && decode(&jwk.x).is_some_and(|jwk_x| jwk_x == x)
+        }
+        _ => false,
+    };
+
+    if matches {
+        Ok(())
+    } else {
+        Err(X5cError::KeyMismatch)
+    }
+}
+
+fn trim_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{BasicConstraints as RcgenConstraints, CertificateParams, IsCa, KeyPair};
+    use serde_json::json;
+
+    use super::*;
+
+    struct TestChain {
+        root_der: Vec<u8>,
+        intermediate_der: Vec<u8>,
+        leaf_der: Vec<u8>,
+        leaf_key: KeyPair,
+    }
+
+    fn ca_params(name: &str) -> CertificateParams {
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(RcgenConstraints::Unconstrained);
+        params
+    }
+
+    fn test_chain() -> TestChain {
+        let root_key = KeyPair::generate().unwrap();
+        let root = ca_params("Test Root CA").self_signed(&root_key).unwrap();
+
+        let intermediate_key = KeyPair::generate().unwrap();
+        let intermediate = ca_params("Test Intermediate CA")
+            .signed_by(&intermediate_key, &root, &root_key)
+            .unwrap();
+
+        let leaf_key = KeyPair::generate().unwrap();
+        let mut leaf_params = CertificateParams::new(vec![]).unwrap();
+        leaf_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Token Signing");
+        let leaf = leaf_params
+            .signed_by(&leaf_key, &intermediate, &intermediate_key)
+            .unwrap();
+
+        TestChain {
+            root_der: root.der().to_vec(),
+            intermediate_der: intermediate.der().to_vec(),
+            leaf_der: leaf.der().to_vec(),
+            leaf_key,
+        }
+    }
+
+    fn jwk_for(chain: &TestChain, x5c: &[&[u8]]) -> jwk::Jwk {
+        // rcgen's default key pairs are P-256: SPKI ends in the uncompressed point
+        let point = &chain.leaf_key.public_key_raw();
+        serde_json::from_value(json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": "x5c-key",
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+            "x5c": x5c.iter().map(|der| STANDARD.encode(der)).collect::<Vec<_>>(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn accepts_chain_to_trusted_root() {
+        let chain = test_chain();
+        let policy = X5cPolicy::from_der([&chain.root_der]).unwrap();
+
+        let jwk = jwk_for(&chain, &[&chain.leaf_der, &chain.intermediate_der]);
+        policy.validate(&jwk).unwrap();
+
+        let jwk = jwk_for(
+            &chain,
+            &[&chain.leaf_der, &chain.intermediate_der, &chain.root_der],
+        );
+        policy.validate(&jwk).unwrap();
+    }
+
+    #[test]
+    fn rejects_chain_to_unknown_root() {
+        let chain = test_chain();
+        let other = test_chain();
+        let policy = X5cPolicy::from_der([&other.root_der]).unwrap();
+
+        let jwk = jwk_for(&chain, &[&chain.leaf_der, &chain.intermediate_der]);
+        assert!(matches!(
+            policy.validate(&jwk),
+            Err(X5cError::UntrustedChain)
+        ));
+    }
+
+    #[test]
+    fn rejects_broken_chain() {
+        let chain = test_chain();
+        let other = test_chain();
+        let policy = X5cPolicy::from_der([&chain.root_der]).unwrap();
+
+        let jwk = jwk_for(&chain, &[&chain.leaf_der, &other.intermediate_der]);
+        assert!(matches!(
+            policy.validate(&jwk),
+            Err(X5cError::InvalidSignature { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_certificates_outside_validity_period() {
+        let chain = test_chain();
+        let mut policy = X5cPolicy::from_der([&chain.root_der]).unwrap();
+        // rcgen certificates are valid from 1975 onwards
+        policy.validation_time = Some(SystemTime::UNIX_EPOCH);
+
+        let jwk = jwk_for(&chain, &[&chain.leaf_der, &chain.intermediate_der]);
+        assert!(matches!(
+            policy.validate(&jwk),
+            Err(X5cError::OutsideValidity { .. })
+        ));
+
+        policy.check_validity = false;
+        policy.validate(&jwk).unwrap();
+    }
+
+    #[test]
+    fn rejects_key_not_matching_leaf_certificate() {
+        let chain = test_chain();
+        let other = test_chain();
+        let policy = X5cPolicy::from_der([&chain.root_der]).unwrap();
+
+        let jwk = jwk_for(&other, &[&chain.leaf_der, &chain.intermediate_der]);
+        assert!(matches!(policy.validate(&jwk), Err(X5cError::KeyMismatch)));
+    }
+
+    #[test]
+    fn rsa_leaf_key_must_match_n_and_e() {
+        use rsa::{pkcs8::EncodePrivateKey, traits::PublicKeyParts};
+
+        let intermediate_key = KeyPair::generate().unwrap();
+        let intermediate = ca_params("Test Intermediate CA")
+            .self_signed(&intermediate_key)
+            .unwrap();
+
+        let private_key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let pkcs8 = private_key.to_pkcs8_der().unwrap();
+        let leaf_key = KeyPair::from_pkcs8_der_and_sign_algo(
+            &pkcs8.as_bytes().into(),
+            &rcgen::PKCS_RSA_SHA256,
+        )
+        .unwrap();
+        let leaf = CertificateParams::new(vec![])
+            .unwrap()
+            .signed_by(&leaf_key, &intermediate, &intermediate_key)
+            .unwrap();
+        let policy = X5cPolicy::from_der([intermediate.der()]).unwrap();
+
+        let public_key = private_key.to_public_key();
+        let rsa_jwk = |n: &[u8]| -> jwk::Jwk {
+            serde_json::from_value(json!({
+                "kty": "RSA",
+                "kid": "rsa-x5c",
+                "n": URL_SAFE_NO_PAD.encode(n),
+                "e": URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+                "x5c": [STANDARD.encode(leaf.der())],
+            }))
+            .unwrap()
+        };
+
+        policy
+            .validate(&rsa_jwk(&public_key.n().to_bytes_be()))
+            .unwrap();
+        assert!(matches!(
+            policy.validate(&rsa_jwk(&[1, 2, 3])),
+            Err(X5cError::KeyMismatch)
+        ));
+    }
+
+    #[test]
+    fn keys_without_x5c_are_accepted_unless_required() {
+        let chain = test_chain();
+        let mut policy = X5cPolicy::from_der([&chain.root_der]).unwrap();
+        let mut jwk = jwk_for(&chain, &[]);
+        jwk.common.x509_chain = None;
+
+        policy.validate(&jwk).unwrap();
+        policy.require_x5c = true;
+        assert!(matches!(policy.validate(&jwk), Err(X5cError::MissingChain)));
+    }
+
+    #[test]
+    fn key_policy_rejects_keys_with_untrusted_chain() {
+        use crate::{JwkError, Jwks, JwksError, KeyPolicy};
+
+        let chain = test_chain();
+        let other = test_chain();
+        let policy = KeyPolicy {
+            x5c: Some(X5cPolicy::from_der([&other.root_der]).unwrap()),
+            ..Default::default()
+        };
+        let jwk_set = jwk::JwkSet {
+            keys: vec![jwk_for(&chain, &[&chain.leaf_der, &chain.intermediate_der])],
+        };
+
+        assert!(Jwks::from_jwk_set(jwk_set.clone()).is_ok());
+        let jwks = Jwks::from_jwk_set_with_policy(jwk_set.clone(), &policy).unwrap();
+        assert!(jwks.is_empty());
+        assert!(matches!(
+            jwks.rejected()[0].error,
+            JwkError::X5cError {
+                error: X5cError::UntrustedChain,
+                ..
+            }
+        ));
+
+        let policy = KeyPolicy {
+            fail_on_violation: true,
+            ..policy
+        };
+        assert!(matches!(
+            Jwks::from_jwk_set_with_policy(jwk_set, &policy),
+            Err(JwksError::KeyError(JwkError::X5cError {
+                error: X5cError::UntrustedChain,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn fills_missing_params_from_leaf_certificate() {
+        use jsonwebtoken::{decode, encode, Algorithm, EncodingKey, Header, Validation};
+
+        let chain = test_chain();
+        let mut key = json!({
+            "kid": "x5c-only",
+            "alg": "ES256",
+            "x5c": [STANDARD.encode(&chain.leaf_der)],
+        });
+        assert!(fill_params_from_x5c(&mut key, false));
+        let jwk_set: jwk::JwkSet = serde_json::from_value(json!({ "keys": [key] })).unwrap();
+        let jwks = crate::Jwks::from_jwk_set(jwk_set).unwrap();
+
+        let encoding_key =
+            EncodingKey::from_ec_pem(chain.leaf_key.serialize_pem().as_bytes()).unwrap();
+        let claims = json!({ "sub": "x5c", "exp": jsonwebtoken::get_current_timestamp() + 60 });
+        let token = encode(&Header::new(Algorithm::ES256), &claims, &encoding_key).unwrap();
+        let decoded = decode::<serde_json::Value>(
+            &token,
+            &jwks.get("x5c-only").unwrap().decoding_key,
+            &Validation::new(Algorithm::ES256),
+        )
+        .unwrap();
+        assert_eq!(decoded.claims, claims);
+    }
+
+    #[test]
+    fn does_not_overwrite_present_params_or_other_key_types() {
+        let chain = test_chain();
+        let mut key = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": "present",
+            "y": "present",
+            "x5c": [STANDARD.encode(&chain.leaf_der)],
+        });
+        assert!(!fill_params_from_x5c(&mut key, false));
+        assert_eq!(key["x"], "present");
+
+        let mut key = json!({
+            "kty": "RSA",
+            "x5c": [STANDARD.encode(&chain.leaf_der)],
+        });
+        assert!(!fill_params_from_x5c(&mut key, false));
+        assert!(key.get("n").is_none());
+    }
+
+    #[test]
+    fn entry_falls_back_to_leaf_certificate_for_empty_params() {
+        let chain = test_chain();
+        let mut jwk = jwk_for(&chain, &[&chain.leaf_der]);
+        if let jwk::AlgorithmParameters::EllipticCurve(params) = &mut jwk.algorithm {
+            params.x.clear();
+            params.y.clear();
+        }
+
+        let entry = crate::JwkEntry::from_jsonwebkey_ref(&jwk).unwrap();
+        assert_eq!(entry.kid, "x5c-key");
+    }
+
+    #[test]
+    fn replaces_stale_params_only_with_validated_chain() {
+        use crate::{JwkEntry, JwkError, KeyPolicy};
+
+        let chain = test_chain();
+        let other = test_chain();
+        // the parameters of a previous key, next to the current certificate
+        let jwk = jwk_for(&other, &[&chain.leaf_der, &chain.intermediate_der]);
+        let x = |entry: &JwkEntry| match &entry.jwk.params {
+            jwk::AlgorithmParameters::EllipticCurve(params) => params.x.clone(),
+            _ => unreachable!(),
+        };
+        let leaf_x = URL_SAFE_NO_PAD.encode(&chain.leaf_key.public_key_raw()[1..33]);
+
+        // without a policy the certificate is not trusted over the parameters
+        let entry = JwkEntry::from_jsonwebkey_ref(&jwk).unwrap();
+        assert_ne!(x(&entry), leaf_x);
+
+        let trusted = KeyPolicy {
+            x5c: Some(X5cPolicy::from_der([&chain.root_der]).unwrap()),
+            ..Default::default()
+        };
+        let entry = JwkEntry::from_jsonwebkey_with_policy(&jwk, &trusted).unwrap();
+        assert_eq!(x(&entry), leaf_x);
+
+        let untrusted = KeyPolicy {
+            x5c: Some(X5cPolicy::from_der([&other.root_der]).unwrap()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            JwkEntry::from_jsonwebkey_with_policy(&jwk, &untrusted),
+            Err(JwkError::X5cError {
+                error: X5cError::UntrustedChain,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_thumbprints_not_matching_leaf_certificate() {
+        use crate::{JwkEntry, JwkError, KeyPolicy};
+
+        let chain = test_chain();
+        let mut jwk = jwk_for(&chain, &[&chain.leaf_der, &chain.intermediate_der]);
+        jwk.common.x509_sha1_fingerprint =
+            Some(URL_SAFE_NO_PAD.encode(Sha1::digest(&chain.leaf_der)));
+        jwk.common.x509_sha256_fingerprint =
+            Some(URL_SAFE_NO_PAD.encode(Sha256::digest(&chain.leaf_der)));
+        assert!(check_thumbprints(&jwk).unwrap().is_some());
+
+        let root_s256 = URL_SAFE_NO_PAD.encode(Sha256::digest(&chain.root_der));
+        jwk.common.x509_sha256_fingerprint = Some(root_s256.clone());
+        let policy = KeyPolicy {
+            x5c: Some(X5cPolicy::from_der([&chain.root_der]).unwrap()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            JwkEntry::from_jsonwebkey_with_policy(&jwk, &policy),
+            Err(JwkError::X5cError {
+                error: X5cError::ThumbprintMismatch("x5t#S256"),
+                ..
+            })
+        ));
+
+        // mismatching thumbprints are refused without an x5c policy too
+        assert!(matches!(
+            JwkEntry::from_jsonwebkey_ref(&jwk),
+            Err(JwkError::X5cError {
+                error: X5cError::ThumbprintMismatch("x5t#S256"),
+                ..
+            })
+        ));
+        let jwks = crate::Jwks::from_jwk_set(jwk::JwkSet {
+            keys: vec![jwk.clone()],
+        })
+        .unwrap();
+        assert!(jwks.is_empty());
+        assert_eq!(jwks.rejected()[0].kid.as_deref(), Some("x5c-key"));
+        assert!(jwks.find_by_x5t(&root_s256).is_none());
+
+        // thumbprints of an unreadable certificate are dropped
+        jwk.common.x509_chain = Some(vec!["not base64!".to_string()]);
+        let entry = JwkEntry::from_jsonwebkey_ref(&jwk).unwrap();
+        assert_eq!(entry.jwk.x5t, None);
+        assert_eq!(entry.jwk.x5t_s256, None);
+    }
+
+    #[test]
+    fn finds_key_by_computed_thumbprint() {
+        let chain = test_chain();
+        let jwk_set = jwk::JwkSet {
+            keys: vec![jwk_for(&chain, &[&chain.leaf_der])],
+        };
+        let jwks = crate::Jwks::from_jwk_set(jwk_set).unwrap();
+
+        let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::ES256);
+        header.x5t = Some(URL_SAFE_NO_PAD.encode(Sha1::digest(&chain.leaf_der)));
+        assert!(jwks.key_for_header(&header).is_some());
+
+        header.x5t = None;
+        header.x5t_s256 = Some(URL_SAFE_NO_PAD.encode(Sha256::digest(&chain.leaf_der)));
+        assert!(jwks.key_for_header(&header).is_some());
+
+        header.kid = Some("unknown".to_string());
+        assert!(jwks.key_for_header(&header).is_none());
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn fetched_jwks_accepts_keys_published_only_as_x5c() {
+        let chain = test_chain();
+        let response = crate::FetchResponse {
+            body: json!({
+                "keys": [{
+                    "kty": "EC",
+                    "kid": "x5c-only",
+                    "x5c": [STANDARD.encode(&chain.leaf_der)],
+                }]
+            })
+            .to_string()
+            .into_bytes(),
+            ..Default::default()
+        };
+
+        let jwks = crate::http::parse_jwks(&response, &crate::KeyPolicy::default()).unwrap();
+        assert!(jwks.get("x5c-only").is_some());
+    }
+}